@@ -0,0 +1,113 @@
+struct DispatchParams {
+    inv_view_proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    camera_origin: vec4<f32>,
+    debug: vec4<u32>,
+    prev_view_proj: mat4x4<f32>,
+    frame: vec4<u32>,
+    jitter: vec4<f32>,
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    zenith_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    fog: vec4<f32>,
+    lod: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> pc: DispatchParams;
+@group(0) @binding(1) var<storage, read> frame: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> history_a: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> history_b: array<vec4<f32>>;
+@group(0) @binding(4) var out_tex: texture_storage_2d<rgba8unorm, write>;
+
+// History buffers ping-pong on the frame index, so reprojected reads never race the writes.
+fn read_history(idx: u32) -> vec3<f32> {
+    if ((pc.frame.z & 1u) == 0u) {
+        return history_a[idx].rgb;
+    }
+    return history_b[idx].rgb;
+}
+
+fn write_history(idx: u32, color: vec3<f32>) {
+    if ((pc.frame.z & 1u) == 0u) {
+        history_b[idx] = vec4(color, 1.0);
+    } else {
+        history_a[idx] = vec4(color, 1.0);
+    }
+}
+
+fn pixel_index(p: vec2<i32>) -> u32 {
+    let size = vec2<i32>(pc.frame.xy);
+    let c = clamp(p, vec2(0), size - 1);
+    return u32(c.y * size.x + c.x);
+}
+
+fn sample_history(uv: vec2<f32>) -> vec3<f32> {
+    let p = uv * vec2<f32>(pc.frame.xy) - 0.5;
+    let i = vec2<i32>(floor(p));
+    let f = fract(p);
+    let top = mix(read_history(pixel_index(i)), read_history(pixel_index(i + vec2(1, 0))), f.x);
+    let bottom = mix(read_history(pixel_index(i + vec2(0, 1))), read_history(pixel_index(i + vec2(1, 1))), f.x);
+    return mix(top, bottom, f.y);
+}
+
+fn rgb_to_ycocg(c: vec3<f32>) -> vec3<f32> {
+    return vec3(
+        0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+        0.5 * c.r - 0.5 * c.b,
+        -0.25 * c.r + 0.5 * c.g - 0.25 * c.b,
+    );
+}
+
+fn ycocg_to_rgb(c: vec3<f32>) -> vec3<f32> {
+    return vec3(c.x + c.y - c.z, c.x + c.z, c.x - c.y - c.z);
+}
+
+// Screen uv of this pixel's surface in the previous frame.
+fn reproject(uv: vec2<f32>, depth: f32) -> vec2<f32> {
+    let ndc = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    var prev_clip: vec4<f32>;
+    if (depth > 0.0) {
+        let world = pc.inv_view_proj * vec4(ndc, depth, 1.0);
+        prev_clip = pc.prev_view_proj * vec4(world.xyz / world.w, 1.0);
+    } else {
+        // Sky is at infinity, only the view direction matters.
+        let near = pc.inv_view_proj * vec4(ndc, 1.0, 1.0);
+        let dir = near.xyz / near.w - pc.camera_origin.xyz;
+        prev_clip = pc.prev_view_proj * vec4(dir, 0.0);
+    }
+    let prev_ndc = prev_clip.xy / prev_clip.w;
+    return vec2(prev_ndc.x * 0.5 + 0.5, 0.5 - prev_ndc.y * 0.5);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = pc.frame.xy;
+    if (any(id.xy >= size)) { return; }
+
+    let idx = id.y * size.x + id.x;
+    let current = frame[idx];
+
+    var result = current.rgb;
+    if (pc.frame.w != 0u) {
+        var min_c = vec3(1e9);
+        var max_c = vec3(-1e9);
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let c = rgb_to_ycocg(frame[pixel_index(vec2<i32>(id.xy) + vec2(x, y))].rgb);
+                min_c = min(min_c, c);
+                max_c = max(max_c, c);
+            }
+        }
+
+        let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+        let prev_uv = reproject(uv, current.w);
+        if (all(prev_uv >= vec2(0.0)) && all(prev_uv <= vec2(1.0))) {
+            let history = clamp(rgb_to_ycocg(sample_history(prev_uv)), min_c, max_c);
+            result = mix(ycocg_to_rgb(history), current.rgb, pc.jitter.z);
+        }
+    }
+
+    write_history(idx, result);
+    textureStore(out_tex, id.xy, vec4(result, 1.0));
+}
@@ -3,28 +3,72 @@ struct Node {
 };
 
 struct Material {
-    color : vec3<f32>,
+    color : array<f32, 3>,
     yield_strength : f32,
     density : f32,
-    friction : f32
+    friction : f32,
+    phase : u32,
+    ignition_temperature : f32,
+    melting_temperature : f32,
+    conductivity : f32,
+    texture_layer : i32,
+    pattern : u32,
+    pattern_scale : f32,
+    pattern_strength : f32
 };
 
 struct HitInfo {
+    // -1 when the ray stopped at an interior node, `color` holds its averaged color then.
     materialid: i32,
+    color: vec3<f32>,
     pos: vec3<f32>,
     normal: vec3<f32>,
     steps: i32,
+    level: f32,
 };
 
 struct DispatchParams {
     inv_view_proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
     camera_origin: vec4<f32>,
+    debug: vec4<u32>,
+    prev_view_proj: mat4x4<f32>,
+    frame: vec4<u32>,
+    jitter: vec4<f32>,
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    zenith_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    fog: vec4<f32>,
+    lod: vec4<f32>,
 };
 
 @group(0) @binding(0) var<uniform> pc: DispatchParams;
 @group(0) @binding(1) var<storage, read> nodePool: array<Node>;
 @group(0) @binding(2) var<storage, read> leafData: array<u32>;
-@group(0) @binding(3) var out_tex: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(3) var<storage, read> palette: array<Material>;
+// Color in rgb, clip depth in w, resolved by the TAA pass.
+@group(0) @binding(4) var<storage, read_write> frame: array<vec4<f32>>;
+@group(0) @binding(5) var depth_tex: texture_storage_2d<r32float, write>;
+// x: traversal steps summed over all rays, y: ray count. Only written while benchmarking.
+@group(0) @binding(6) var<storage, read_write> stats: array<atomic<u32>, 2>;
+// Surface texture atlas, TEXTURE_SIZE^2 rgba8 texels per layer, rows of x.
+@group(0) @binding(7) var<storage, read> texels: array<u32>;
+
+const DEBUG_SHADED: u32 = 0u;
+const DEBUG_NORMALS: u32 = 1u;
+const DEBUG_MATERIAL_ID: u32 = 2u;
+const DEBUG_ALBEDO: u32 = 3u;
+const DEBUG_STEPS: u32 = 4u;
+const DEBUG_DEPTH: u32 = 5u;
+const DEBUG_NODE_LEVEL: u32 = 6u;
+
+const DEBUG_DEPTH_RANGE: f32 = 512.0;
+
+const TEXTURE_SIZE: u32 = 16u;
+const PATTERN_NOISE: u32 = 1u;
+const PATTERN_BRICKS: u32 = 2u;
+const SKY_COLOR: vec3<f32> = vec3(0.53, 0.81, 0.98);
 
 
 
@@ -56,6 +100,109 @@ fn viridis(t: f32) -> vec3<f32> {
     return c0+t*(c1+t*(c2+t*(c3+t*(c4+t*(c5+t*c6)))));
 }
 
+fn hash_color(id: u32) -> vec3<f32> {
+    var h = id * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    return vec3<f32>(vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(255u)) / 255.0;
+}
+
+fn material_albedo(id: i32) -> vec3<f32> {
+    let c = palette[u32(id)].color;
+    return vec3(c[0], c[1], c[2]);
+}
+
+// True once a cell of `scale_exp` is smaller than a pixel at the ray's current distance.
+// Cells are 2^(scale_exp - 23) wide in the [1, 2) tree space.
+fn below_pixel(pos: vec3<f32>, origin: vec3<f32>, scale_exp: i32) -> bool {
+    return exp2(f32(scale_exp - 23)) < distance(pos, origin) * pc.lod.x;
+}
+
+fn hash3(p: vec3<i32>) -> f32 {
+    var h = u32(p.x) * 73856093u ^ u32(p.y) * 19349663u ^ u32(p.z) * 83492791u;
+    h = (h ^ (h >> 16u)) * 2246822519u;
+    h = (h ^ (h >> 13u)) * 3266489917u;
+    return f32(h ^ (h >> 16u)) / 4294967295.0;
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = vec3<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let x00 = mix(hash3(i), hash3(i + vec3(1, 0, 0)), u.x);
+    let x10 = mix(hash3(i + vec3(0, 1, 0)), hash3(i + vec3(1, 1, 0)), u.x);
+    let x01 = mix(hash3(i + vec3(0, 0, 1)), hash3(i + vec3(1, 0, 1)), u.x);
+    let x11 = mix(hash3(i + vec3(0, 1, 1)), hash3(i + vec3(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z);
+}
+
+// 0 in mortar, 1 on brick faces. `uv` is in brick cells, every other row is offset by half.
+fn brick_pattern(uv: vec2<f32>) -> f32 {
+    let row = floor(uv.y * 2.0);
+    let cell = fract(vec2(uv.x + row * 0.5, uv.y * 2.0));
+    let edge = min(min(cell.x, 1.0 - cell.x) * 2.0, min(cell.y, 1.0 - cell.y));
+    return smoothstep(0.04, 0.1, edge);
+}
+
+// Albedo with the material's texture and pattern applied. `pos` is the hit point in voxels,
+// `normal` picks the two axes the face is textured along.
+fn surface_albedo(id: i32, pos: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let m = palette[u32(id)];
+    var albedo = vec3(m.color[0], m.color[1], m.color[2]);
+    let n = abs(normal);
+    var uv = pos.xy;
+    if (n.x > 0.5) { uv = pos.zy; } else if (n.y > 0.5) { uv = pos.xz; }
+
+    // Layers past the texel buffer are left untextured instead of reading out of bounds.
+    let layers = arrayLength(&texels) / (TEXTURE_SIZE * TEXTURE_SIZE);
+    if (m.texture_layer >= 0 && u32(m.texture_layer) < layers) {
+        let texel = vec2<u32>(fract(uv) * f32(TEXTURE_SIZE)) % TEXTURE_SIZE;
+        let index = u32(m.texture_layer) * TEXTURE_SIZE * TEXTURE_SIZE + texel.y * TEXTURE_SIZE + texel.x;
+        albedo *= unpack4x8unorm(texels[index]).rgb;
+    }
+
+    let scale = max(m.pattern_scale, 0.001);
+    var detail = 1.0;
+    if (m.pattern == PATTERN_NOISE) {
+        // Nudged inside the voxel so faces of one voxel agree along edges.
+        let p = (pos - normal * 0.01) / scale;
+        detail = value_noise(p) * 0.65 + value_noise(p * 2.7) * 0.35;
+    } else if (m.pattern == PATTERN_BRICKS) {
+        detail = brick_pattern(uv / scale);
+    }
+    return albedo * (1.0 - m.pattern_strength * (1.0 - detail));
+}
+
+fn hit_albedo(hit: HitInfo, pos: vec3<f32>) -> vec3<f32> {
+    if (hit.materialid < 0) { return hit.color; }
+    return surface_albedo(hit.materialid, pos, hit.normal);
+}
+
+fn sky(dir: vec3<f32>) -> vec3<f32> {
+    let sun_dir = pc.sun_direction.xyz;
+    let sun = pc.sun_color.rgb * pc.sun_color.w;
+    let height = dir.y;
+
+    var color = mix(pc.horizon_color.rgb, pc.zenith_color.rgb, sqrt(clamp(height, 0.0, 1.0)));
+    // Darken towards a ground tone below the horizon.
+    color = mix(color, pc.horizon_color.rgb * 0.35, clamp(-height * 4.0, 0.0, 1.0));
+
+    let mu = max(dot(dir, sun_dir), 0.0);
+    color += sun * (pow(mu, 64.0) * 0.35 + pow(mu, 8.0) * 0.1);
+
+    let disk_cos = pc.sun_direction.w;
+    color += sun * smoothstep(disk_cos - 0.0004, disk_cos, mu) * 4.0;
+    return color;
+}
+
+fn apply_fog(color: vec3<f32>, dist: f32, dir: vec3<f32>) -> vec3<f32> {
+    let fog_dist = max(dist - pc.fog.y, 0.0);
+    let amount = 1.0 - exp(-fog_dist * pc.fog.x);
+    let scatter = pow(max(dot(dir, pc.sun_direction.xyz), 0.0), 8.0) * pc.fog.z;
+    let fog_color = mix(pc.horizon_color.rgb, pc.sun_color.rgb * pc.sun_color.w, scatter);
+    return mix(color, fog_color, amount);
+}
+
 fn get_mirrored_pos(pos: vec3<f32>, dir: vec3<f32>, rangeCheck: bool) -> vec3<f32> {
     var mirrored = bitcast<vec3<f32>>(bitcast<vec3<u32>>(pos) ^ vec3<u32>(0x7FFFFFu));
     if (rangeCheck && (any(pos < vec3(1.0)) || any(pos >= vec3(2.0)))) {
@@ -95,9 +242,10 @@ struct Ray {
 };
 
 fn get_primary_ray(screenPos: vec2<u32>) -> Ray {
-    let tex_size = textureDimensions(out_tex);
-    var uv = (vec2<f32>(screenPos) + 0.5) / vec2<f32>(tex_size);
-    uv = uv * 2.0 - 1.0;
+    let tex_size = pc.frame.xy;
+    var uv = (vec2<f32>(screenPos) + 0.5 + pc.jitter.xy) / vec2<f32>(tex_size);
+    // Texture rows go top to bottom, NDC y goes bottom to top.
+    uv = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
     let world = pc.inv_view_proj * vec4(uv.x, uv.y, 1.0, 1.0);
     let worldPos = world.xyz / world.w;
 
@@ -110,8 +258,11 @@ fn get_primary_ray(screenPos: vec2<u32>) -> Ray {
 fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
     var hit: HitInfo;
     hit.materialid = 0;
+    hit.color = vec3(0.0);
     hit.normal = vec3(0.0);
     hit.pos = vec3(0.0);
+    hit.steps = 0;
+    var levelSum = 0.0;
 
     var stack: array<u32, 11>;
     var scaleExp: i32 = 21;
@@ -131,6 +282,7 @@ fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
     var sideDist: vec3<f32>;
     var childIdx: u32;
     var skipNextHit = true;
+    var lodHit = false;
 
     if (any(pos != origin)) {
         let t0 = (vec3(2.0) - origin) * invDir;
@@ -146,6 +298,10 @@ fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
         childIdx = get_node_cell_index(pos, scaleExp) ^ mirrorMask;
 
         while (check_pop_mask(node, childIdx) && !is_leaf(node) && scaleExp >= 2) {
+            if (pc.lod.x > 0.0 && !skipNextHit && below_pixel(pos, origin, scaleExp)) {
+                lodHit = true;
+                break;
+            }
             stack[scaleExp >> 1] = nodeIdx;
             nodeIdx = child_ptr(node) + popcnt_var64(node, childIdx);
             node = nodePool[nodeIdx];
@@ -153,7 +309,7 @@ fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
             childIdx = get_node_cell_index(pos, scaleExp) ^ mirrorMask;
         }
 
-        if (check_pop_mask(node, childIdx) && is_leaf(node) && !skipNextHit) {
+        if (lodHit || (check_pop_mask(node, childIdx) && is_leaf(node) && !skipNextHit)) {
             break;
         }
 
@@ -162,6 +318,7 @@ fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
             (node.packed_data[2] >> (childIdx & 0x2Au) & 0x00330033u) == 0u) {
             advScaleExp += 1;
         }
+        levelSum += f32(advScaleExp);
 
         let edgePos = floor_scale(pos, advScaleExp);
 
@@ -183,10 +340,17 @@ fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
         skipNextHit = false;
         hit.steps = i;
     }
+    hit.level = levelSum / f32(max(hit.steps + 1, 1));
 
-    if (is_leaf(node) && scaleExp <= 21) {
+    if ((lodHit || is_leaf(node)) && scaleExp <= 21) {
         pos = get_mirrored_pos(pos, dir, false);
-        hit.materialid = i32(leafData[child_ptr(node) + popcnt_var64(node, childIdx)]);
+        let index = child_ptr(node) + popcnt_var64(node, childIdx);
+        if (lodHit) {
+            hit.materialid = -1;
+            hit.color = unpack4x8unorm(nodePool[index].packed_data[3]).rgb;
+        } else {
+            hit.materialid = i32(leafData[index]);
+        }
         hit.pos = pos;
         let tmax = min(min(sideDist.x, sideDist.y), sideDist.z);
         hit.normal = select(vec3(0.0), -sign(dir), sideDist <= vec3(tmax));
@@ -196,18 +360,64 @@ fn raycast(origin_in: vec3<f32>, dir: vec3<f32>) -> HitInfo {
 
 @compute @workgroup_size(8, 8)
 fn main(@builtin(global_invocation_id) screenPos: vec3<u32>) {
-    let tex_size = textureDimensions(out_tex);
+    let tex_size = pc.frame.xy;
     if (any(screenPos.xy >= tex_size)) { return; }
 
     let ray = get_primary_ray(screenPos.xy);
     let scale = 1.0 / f32(1u << u32(pc.camera_origin.w));
     let origin = vec3(0.0) * scale + ray.pos * scale + 1.0;
     let hit = raycast(origin, ray.dir);
-    var albedo = vec3(0.53, 0.81, 0.98);
-    if (hit.materialid != 0) {
-        albedo = hit.normal * 0.5 + 0.5;
-    } else {
-//        albedo = viridis((f32(hit.steps) / 50));
+    let is_hit = hit.materialid != 0;
+    let dist = length(hit.pos - origin) / scale;
+    let voxel_pos = (hit.pos - 1.0) / scale;
+    if (pc.debug.y != 0u) {
+        atomicAdd(&stats[0], u32(hit.steps));
+        atomicAdd(&stats[1], 1u);
+    }
+
+    var color = SKY_COLOR;
+    switch pc.debug.x {
+        case DEBUG_NORMALS: {
+            if (is_hit) { color = hit.normal * 0.5 + 0.5; }
+        }
+        case DEBUG_MATERIAL_ID: {
+            if (is_hit) { color = select(hash_color(u32(hit.materialid)), hit.color, hit.materialid < 0); }
+        }
+        case DEBUG_ALBEDO: {
+            if (is_hit) { color = hit_albedo(hit, voxel_pos); }
+        }
+        case DEBUG_STEPS: {
+            color = viridis(f32(hit.steps) / f32(MAX_STEPS));
+        }
+        case DEBUG_DEPTH: {
+            color = vec3(0.0);
+            if (is_hit) { color = vec3(1.0 - clamp(dist / DEBUG_DEPTH_RANGE, 0.0, 1.0)); }
+        }
+        case DEBUG_NODE_LEVEL: {
+            // Average log2 size (in voxels) of the cells the ray stepped over.
+            let cell_log2 = hit.level - 23.0 + pc.camera_origin.w;
+            color = viridis(clamp(cell_log2 / pc.camera_origin.w, 0.0, 1.0));
+        }
+        // DEBUG_SHADED. The normals view this used to be is DEBUG_NORMALS now.
+        default: {
+            color = sky(ray.dir);
+            if (is_hit) {
+                let sun = pc.sun_color.rgb * pc.sun_color.w;
+                let diffuse = max(dot(hit.normal, pc.sun_direction.xyz), 0.0);
+                let ambient = mix(pc.horizon_color.rgb, pc.zenith_color.rgb, hit.normal.y * 0.5 + 0.5);
+                let albedo = hit_albedo(hit, voxel_pos);
+                let lit = albedo * (ambient * 0.35 + sun * diffuse * 0.75);
+                color = apply_fog(lit, dist, ray.dir);
+            }
+        }
+    }
+
+    // Reverse-Z clip depth matching the Camera3d projection, 0.0 is the far plane.
+    var depth = 0.0;
+    if (is_hit) {
+        let clip = pc.view_proj * vec4((hit.pos - 1.0) / scale, 1.0);
+        depth = clip.z / clip.w;
     }
-   textureStore(out_tex, screenPos.xy, vec4(albedo, 1.0));
+    frame[screenPos.y * tex_size.x + screenPos.x] = vec4(color, depth);
+    textureStore(depth_tex, screenPos.xy, vec4(depth, 0.0, 0.0, 0.0));
 }
\ No newline at end of file
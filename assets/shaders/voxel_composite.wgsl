@@ -0,0 +1,77 @@
+@group(2) @binding(0) var color_texture: texture_2d<f32>;
+@group(2) @binding(1) var color_sampler: sampler;
+@group(2) @binding(2) var depth_texture: texture_2d<f32>;
+// x: filter (0 nearest, 1 bilinear, 2 sharpened), y: sharpness.
+@group(2) @binding(3) var<uniform> upscale: vec4<f32>;
+
+const FILTER_NEAREST: u32 = 0u;
+const FILTER_SHARPENED: u32 = 2u;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+// The quad is a 2x2 rectangle, so its local positions already cover clip space.
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4(vertex.position.xy, 0.0, 1.0);
+    out.uv = vertex.uv;
+    return out;
+}
+
+fn sample_color(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(color_texture, color_sampler, uv, 0.0).rgb;
+}
+
+// Contrast adaptive sharpening over the bilinear upscale, in the spirit of FSR's RCAS.
+fn sharpen(uv: vec2<f32>, texel_size: vec2<f32>, sharpness: f32) -> vec3<f32> {
+    let c = sample_color(uv);
+    let n = sample_color(uv + vec2(0.0, -texel_size.y));
+    let w = sample_color(uv + vec2(-texel_size.x, 0.0));
+    let e = sample_color(uv + vec2(texel_size.x, 0.0));
+    let s = sample_color(uv + vec2(0.0, texel_size.y));
+
+    let min_rgb = min(c, min(min(n, s), min(w, e)));
+    let max_rgb = max(c, max(max(n, s), max(w, e)));
+    let amp = sqrt(clamp(min(min_rgb, 1.0 - max_rgb) / max(max_rgb, vec3(1e-4)), vec3(0.0), vec3(1.0)));
+    let lobe = amp * (-1.0 / mix(8.0, 5.0, clamp(sharpness, 0.0, 1.0)));
+
+    return clamp((c + (n + w + e + s) * lobe) / (1.0 + 4.0 * lobe), vec3(0.0), vec3(1.0));
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    let color_size = vec2<f32>(textureDimensions(color_texture));
+    let depth_size = vec2<f32>(textureDimensions(depth_texture));
+    let texel = vec2<i32>(min(in.uv * depth_size, depth_size - 1.0));
+
+    var color: vec3<f32>;
+    switch u32(upscale.x) {
+        case FILTER_NEAREST: {
+            color = textureLoad(color_texture, vec2<i32>(min(in.uv * color_size, color_size - 1.0)), 0).rgb;
+        }
+        case FILTER_SHARPENED: {
+            color = sharpen(in.uv, 1.0 / color_size, upscale.y);
+        }
+        default: {
+            color = sample_color(in.uv);
+        }
+    }
+
+    var out: FragmentOutput;
+    out.color = vec4(color, 1.0);
+    out.depth = textureLoad(depth_texture, texel, 0).r;
+    return out;
+}
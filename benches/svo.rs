@@ -0,0 +1,160 @@
+use bevy::math::IVec3;
+use criterion::{
+    BatchSize, BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main,
+};
+use mushoku_tensei::config::Node;
+use mushoku_tensei::generation::{WorldGenerator, default_palette};
+use mushoku_tensei::voxel_map::{
+    SECTOR_SCALE, SvoStorage, VoxelWorld, build_chunk_tree, build_tlas, get_morton_key,
+};
+
+/// Synthetic worlds of roughly `size`^3 voxels.
+fn worlds(size: i32) -> Vec<(&'static str, WorldGenerator)> {
+    vec![
+        (
+            "sphere",
+            WorldGenerator::Sphere {
+                center: IVec3::splat(size / 2),
+                radius: size / 2,
+                material: "red".to_string(),
+            },
+        ),
+        (
+            "terrain",
+            WorldGenerator::Terrain {
+                size,
+                max_height: size / 2,
+                feature_size: 32,
+                seed: 7,
+                material: "red".to_string(),
+            },
+        ),
+        (
+            "scatter",
+            WorldGenerator::Scatter {
+                size,
+                count: (size * size) as u32,
+                seed: 7,
+                material: "red".to_string(),
+            },
+        ),
+    ]
+}
+
+const SIZES: [i32; 3] = [64, 128, 256];
+
+fn build(generator: &WorldGenerator) -> VoxelWorld {
+    let mut world = VoxelWorld::default();
+    world.set_palette(&default_palette());
+    generator.generate(&mut world);
+    world
+}
+
+fn chunk_roots(world: &VoxelWorld) -> Vec<(u64, Node)> {
+    let (mut nodes, mut leaf_data) = (Vec::new(), Vec::new());
+    let mut roots: Vec<(u64, Node)> = world
+        .sectors
+        .keys()
+        .filter_map(|&sector| {
+            build_chunk_tree(
+                world,
+                &mut nodes,
+                &mut leaf_data,
+                SECTOR_SCALE,
+                sector << SECTOR_SCALE,
+            )
+            .map(|root| (get_morton_key(sector) << 18, root))
+        })
+        .collect();
+    roots.sort_by_key(|r| r.0);
+    roots
+}
+
+fn morton(c: &mut Criterion) {
+    let positions: Vec<IVec3> = (0..4096)
+        .map(|i| IVec3::new(i * 7 % 1024, i * 13 % 1024, i * 31 % 1024))
+        .collect();
+    let mut group = c.benchmark_group("get_morton_key");
+    group.throughput(Throughput::Elements(positions.len() as u64));
+    group.bench_function("4096 keys", |b| {
+        b.iter(|| {
+            positions
+                .iter()
+                .fold(0u64, |acc, &pos| acc ^ get_morton_key(black_box(pos)))
+        })
+    });
+    group.finish();
+}
+
+fn chunk_tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_chunk_tree");
+    for size in SIZES {
+        for (name, generator) in worlds(size) {
+            let world = build(&generator);
+            group.throughput(Throughput::Elements(world.sectors.len() as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &world, |b, world| {
+                b.iter(|| chunk_roots(world))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn tlas(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_tlas");
+    for size in SIZES {
+        for (name, generator) in worlds(size) {
+            let roots = chunk_roots(&build(&generator));
+            group.bench_with_input(BenchmarkId::new(name, size), &roots, |b, roots| {
+                b.iter_batched(
+                    || roots.clone(),
+                    |roots| build_tlas(roots, &mut Vec::new(), SECTOR_SCALE),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn svo(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_svo");
+    group.sample_size(20);
+    for size in SIZES {
+        for (name, generator) in worlds(size) {
+            let world = build(&generator);
+            let mut storage = SvoStorage::default();
+            group.bench_with_input(BenchmarkId::new(name, size), &world, |b, world| {
+                b.iter(|| world.generate_svo(&mut storage))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn edits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set_voxel");
+    for size in SIZES {
+        let positions: Vec<IVec3> = (0..4096)
+            .map(|i| IVec3::new(i * 37 % size, i * 11 % size, i * 53 % size))
+            .collect();
+        group.throughput(Throughput::Elements(positions.len() as u64));
+        for (name, generator) in worlds(size) {
+            let mut world = build(&generator);
+            let mut mat_id = 0;
+            group.bench_with_input(BenchmarkId::new(name, size), &positions, |b, positions| {
+                // Every iteration undoes the previous one, removing and placing in turn.
+                b.iter(|| {
+                    mat_id ^= 1;
+                    for &pos in positions {
+                        world.set_voxel(pos, mat_id);
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, morton, chunk_tree, tlas, svo, edits);
+criterion_main!(benches);
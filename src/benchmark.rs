@@ -0,0 +1,250 @@
+use crate::camera_path::{CAMERA_PATH_FILE, CameraPath, CameraPathPlayer, PathState};
+use crate::compute::WriteTextureWorker;
+use crate::render::{RenderResolution, VoxelCamera};
+use bevy::prelude::*;
+use bevy_app_compute::prelude::*;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Frames rendered before measuring starts, so shader compilation and uploads are not counted.
+const WARMUP_FRAMES: u32 = 30;
+
+/// Flies `path` at a fixed `frame_rate`, one path step per rendered frame whatever the frame
+/// actually took, so every run renders the same views. Frame times and GPU traversal steps
+/// are collected and written to `output` as JSON when the path ends.
+#[derive(Resource, Debug, Default)]
+pub struct Benchmark {
+    pub path: CameraPath,
+    pub name: String,
+    pub output: PathBuf,
+    pub frame_rate: f32,
+    /// Quit once the results are written, for runs started from the command line.
+    pub exit_when_done: bool,
+    running: bool,
+    frame: u32,
+    frame_times: Vec<f32>,
+    steps: u64,
+    rays: u64,
+}
+
+impl Benchmark {
+    pub fn new(path: CameraPath, name: impl Into<String>) -> Self {
+        Self {
+            path,
+            name: name.into(),
+            output: PathBuf::from("benchmark.json"),
+            frame_rate: 60.0,
+            running: true,
+            ..default()
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchmarkStats {
+    pub frames: usize,
+    pub min_ms: f32,
+    pub avg_ms: f32,
+    pub p99_ms: f32,
+    pub max_ms: f32,
+    pub avg_steps: f32,
+}
+
+impl BenchmarkStats {
+    pub fn compute(frame_times: &[f32], steps: u64, rays: u64) -> Option<Self> {
+        if frame_times.is_empty() {
+            return None;
+        }
+        let mut sorted = frame_times.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let p99 = ((sorted.len() as f32 * 0.99).ceil() as usize).clamp(1, sorted.len()) - 1;
+        Some(Self {
+            frames: sorted.len(),
+            min_ms: sorted[0],
+            avg_ms: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            p99_ms: sorted[p99],
+            max_ms: sorted[sorted.len() - 1],
+            avg_steps: if rays > 0 {
+                steps as f32 / rays as f32
+            } else {
+                0.0
+            },
+        })
+    }
+
+    /// Non-finite values are written as `null`.
+    pub fn to_json(&self, name: &str, resolution: UVec2) -> String {
+        let report = BenchmarkReport {
+            name,
+            resolution: resolution.to_array(),
+            frames: self.frames,
+            frame_time_ms: FrameTimes {
+                min: self.min_ms,
+                avg: self.avg_ms,
+                p99: self.p99_ms,
+                max: self.max_ms,
+            },
+            avg_traversal_steps: self.avg_steps,
+        };
+        serde_json::to_string_pretty(&report).expect("benchmark report serializes") + "\n"
+    }
+}
+
+#[derive(Serialize)]
+struct FrameTimes {
+    min: f32,
+    avg: f32,
+    p99: f32,
+    max: f32,
+}
+
+/// Layout of the JSON results file.
+#[derive(Serialize)]
+struct BenchmarkReport<'a> {
+    name: &'a str,
+    resolution: [u32; 2],
+    frames: usize,
+    frame_time_ms: FrameTimes,
+    avg_traversal_steps: f32,
+}
+
+/// Run condition that holds the simulations while a benchmark runs, so every run renders
+/// the same world.
+pub fn not_benchmarking(benchmark: Option<Res<Benchmark>>) -> bool {
+    benchmark.is_none_or(|b| !b.running)
+}
+
+/// F7 benchmarks the camera path in memory, or the one saved at `CAMERA_PATH_FILE`.
+pub fn benchmark_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut player: ResMut<CameraPathPlayer>,
+    mut benchmark: ResMut<Benchmark>,
+) {
+    if !keyboard.just_pressed(KeyCode::F7) || benchmark.running {
+        return;
+    }
+    let (path, name) = if player.path.keyframes.is_empty() {
+        match CameraPath::load(CAMERA_PATH_FILE) {
+            Ok(path) => (path, CAMERA_PATH_FILE),
+            Err(err) => {
+                println!("Failed to load camera path {}: {}", CAMERA_PATH_FILE, err);
+                return;
+            }
+        }
+    } else {
+        (player.path.clone(), "recorded camera path")
+    };
+    player.state = PathState::Idle;
+    *benchmark = Benchmark::new(path, name);
+}
+
+/// Moves the camera along the benchmark path and accumulates the previous frame's stats.
+pub fn run_benchmark(
+    time: Res<Time<Real>>,
+    resolution: Res<RenderResolution>,
+    mut benchmark: ResMut<Benchmark>,
+    mut worker: ResMut<AppComputeWorker<WriteTextureWorker>>,
+    mut camera_q: Query<&mut Transform, With<VoxelCamera>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !benchmark.running {
+        return;
+    }
+    if benchmark.frame == 0 {
+        println!(
+            "Benchmark {}: {} keyframes, {:.1}s",
+            benchmark.name,
+            benchmark.path.keyframes.len(),
+            benchmark.path.duration()
+        );
+    }
+    if benchmark.frame > WARMUP_FRAMES && worker.ready() {
+        let stats = worker.read_vec::<u32>("stats");
+        if let [steps, rays] = stats[..] {
+            benchmark.steps += steps as u64;
+            benchmark.rays += rays as u64;
+        }
+        benchmark.frame_times.push(time.delta_secs() * 1000.0);
+    }
+    worker.write_slice("stats", &[0u32, 0u32]);
+
+    let path_time = benchmark.frame.saturating_sub(WARMUP_FRAMES) as f32 / benchmark.frame_rate;
+    if let (Ok(mut transform), Some(sampled)) =
+        (camera_q.single_mut(), benchmark.path.sample(path_time))
+    {
+        *transform = sampled;
+    }
+    benchmark.frame += 1;
+    if path_time <= benchmark.path.duration() {
+        return;
+    }
+
+    benchmark.running = false;
+    let Some(stats) =
+        BenchmarkStats::compute(&benchmark.frame_times, benchmark.steps, benchmark.rays)
+    else {
+        println!("Benchmark {} finished without samples", benchmark.name);
+        return;
+    };
+    println!(
+        "Benchmark {}: {} frames, min {:.2} ms, avg {:.2} ms, p99 {:.2} ms, {:.1} steps/ray",
+        benchmark.name, stats.frames, stats.min_ms, stats.avg_ms, stats.p99_ms, stats.avg_steps
+    );
+    let json = stats.to_json(&benchmark.name, resolution.0);
+    match std::fs::write(&benchmark.output, json) {
+        Ok(()) => println!("Wrote {}", benchmark.output.display()),
+        Err(err) => println!("Failed to write {}: {}", benchmark.output.display(), err),
+    }
+    if benchmark.exit_when_done {
+        exit.write(AppExit::Success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_use_the_nearest_rank_p99() {
+        assert_eq!(BenchmarkStats::compute(&[], 0, 0), None);
+
+        let times: Vec<f32> = (1..=200).rev().map(|ms| ms as f32).collect();
+        let stats = BenchmarkStats::compute(&times, 300, 100).unwrap();
+        assert_eq!(stats.frames, 200);
+        assert_eq!((stats.min_ms, stats.max_ms), (1.0, 200.0));
+        assert_eq!(stats.avg_ms, 100.5);
+        // 99% of 200 frames are at or below the 198th.
+        assert_eq!(stats.p99_ms, 198.0);
+        assert_eq!(stats.avg_steps, 3.0);
+
+        let stats = BenchmarkStats::compute(&[4.0], 10, 0).unwrap();
+        assert_eq!((stats.p99_ms, stats.avg_steps), (4.0, 0.0));
+        let stats = BenchmarkStats::compute(&[1.0, 2.0, 3.0], 0, 0).unwrap();
+        assert_eq!(stats.p99_ms, 3.0);
+    }
+
+    #[test]
+    fn json_is_valid_for_any_name_and_value() {
+        let stats = BenchmarkStats {
+            frames: 2,
+            min_ms: 1.5,
+            avg_ms: f32::NAN,
+            p99_ms: f32::INFINITY,
+            max_ms: 2.5,
+            avg_steps: 12.0,
+        };
+        let json = stats.to_json("a \"quoted\" path\\name", UVec2::new(1920, 1080));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["name"], "a \"quoted\" path\\name");
+        assert_eq!(value["resolution"], serde_json::json!([1920, 1080]));
+        assert_eq!(value["frames"], 2);
+        assert_eq!(value["frame_time_ms"]["min"], 1.5);
+        assert!(value["frame_time_ms"]["avg"].is_null());
+        assert!(value["frame_time_ms"]["p99"].is_null());
+        assert_eq!(value["avg_traversal_steps"], 12.0);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::render::VoxelCamera;
+use bevy::prelude::*;
+use std::io;
+use std::path::Path;
+
+/// Where F5 saves the recorded path and F6 / F7 load it from.
+pub const CAMERA_PATH_FILE: &str = "camera_paths/flythrough.txt";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    /// Seconds since the start of the path.
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+/// Keyframed camera flight. Positions follow a Catmull-Rom spline through the keyframes,
+/// rotations are slerped.
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+impl CameraPath {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Camera transform `time` seconds into the path, clamped to its ends.
+    pub fn sample(&self, time: f32) -> Option<Transform> {
+        let keys = &self.keyframes;
+        let last = keys.len().checked_sub(1)?;
+        if last == 0 {
+            let k = &keys[0];
+            return Some(Transform::from_translation(k.translation).with_rotation(k.rotation));
+        }
+        let next = keys.partition_point(|k| k.time <= time).clamp(1, last);
+        let (a, b) = (&keys[next - 1], &keys[next]);
+        let span = b.time - a.time;
+        let t = if span > 0.0 {
+            ((time - a.time) / span).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let p0 = keys[next.saturating_sub(2)].translation;
+        let p3 = keys[(next + 1).min(last)].translation;
+        let translation = catmull_rom(p0, a.translation, b.translation, p3, t);
+        Some(
+            Transform::from_translation(translation).with_rotation(a.rotation.slerp(b.rotation, t)),
+        )
+    }
+
+    /// One keyframe per line: `time x y z qx qy qz qw`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for k in &self.keyframes {
+            let (t, r) = (k.translation, k.rotation);
+            text += &format!(
+                "{} {} {} {} {} {} {} {}\n",
+                k.time, t.x, t.y, t.z, r.x, r.y, r.z, r.w
+            );
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> io::Result<Self> {
+        let mut keyframes = Vec::new();
+        for (line_index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let [time, x, y, z, qx, qy, qz, qw] = values[..] else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected 8 values", line_index + 1),
+                ));
+            };
+            keyframes.push(CameraKeyframe {
+                time,
+                translation: Vec3::new(x, y, z),
+                rotation: Quat::from_xyzw(qx, qy, qz, qw).normalize(),
+            });
+        }
+        Ok(Self { keyframes })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_text(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_text())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PathState {
+    #[default]
+    Idle,
+    Recording,
+    Playing,
+}
+
+/// Records the live camera into `path`, or plays `path` back in place of the camera controls.
+#[derive(Resource, Debug)]
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    pub state: PathState,
+    /// Seconds between recorded keyframes.
+    pub record_interval: f32,
+    pub looping: bool,
+    time: f32,
+}
+
+impl Default for CameraPathPlayer {
+    fn default() -> Self {
+        Self {
+            path: CameraPath::default(),
+            state: PathState::Idle,
+            record_interval: 0.25,
+            looping: true,
+            time: 0.0,
+        }
+    }
+}
+
+impl CameraPathPlayer {
+    /// Plays the path from the start, loading `CAMERA_PATH_FILE` if none is in memory.
+    /// Returns false if there is nothing to play.
+    pub fn play(&mut self) -> bool {
+        if self.path.keyframes.is_empty() {
+            match CameraPath::load(CAMERA_PATH_FILE) {
+                Ok(path) => self.path = path,
+                Err(err) => {
+                    println!("Failed to load camera path {}: {}", CAMERA_PATH_FILE, err);
+                    return false;
+                }
+            }
+        }
+        self.time = 0.0;
+        self.state = PathState::Playing;
+        println!("Playing camera path");
+        true
+    }
+}
+
+/// F5 starts / stops recording (saved to `CAMERA_PATH_FILE`), F6 starts / stops playback.
+pub fn camera_path_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut player: ResMut<CameraPathPlayer>,
+) {
+    if keyboard.just_pressed(KeyCode::F5) {
+        if player.state == PathState::Recording {
+            player.state = PathState::Idle;
+            match player.path.save(CAMERA_PATH_FILE) {
+                Ok(()) => println!(
+                    "Saved camera path {} ({} keyframes, {:.1}s)",
+                    CAMERA_PATH_FILE,
+                    player.path.keyframes.len(),
+                    player.path.duration()
+                ),
+                Err(err) => println!("Failed to save camera path {}: {}", CAMERA_PATH_FILE, err),
+            }
+        } else {
+            player.path.keyframes.clear();
+            player.time = 0.0;
+            player.state = PathState::Recording;
+            println!("Recording camera path");
+        }
+    }
+    if keyboard.just_pressed(KeyCode::F6) {
+        if player.state == PathState::Playing {
+            player.state = PathState::Idle;
+        } else {
+            player.play();
+        }
+    }
+}
+
+/// Runs after the camera controls, so playback overrides them.
+pub fn update_camera_path(
+    time: Res<Time>,
+    mut player: ResMut<CameraPathPlayer>,
+    mut camera_q: Query<&mut Transform, With<VoxelCamera>>,
+) {
+    let Ok(mut transform) = camera_q.single_mut() else {
+        return;
+    };
+    match player.state {
+        PathState::Idle => {}
+        PathState::Recording => {
+            let now = player.time;
+            let due = player
+                .path
+                .keyframes
+                .last()
+                .is_none_or(|k| now - k.time >= player.record_interval);
+            if due {
+                player.path.keyframes.push(CameraKeyframe {
+                    time: now,
+                    translation: transform.translation,
+                    rotation: transform.rotation,
+                });
+            }
+            player.time += time.delta_secs();
+        }
+        PathState::Playing => {
+            if let Some(sampled) = player.path.sample(player.time) {
+                *transform = sampled;
+            }
+            player.time += time.delta_secs();
+            if player.time > player.path.duration() {
+                if player.looping {
+                    player.time = 0.0;
+                } else {
+                    player.state = PathState::Idle;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> CameraPath {
+        let keyframes = (0..4)
+            .map(|i| CameraKeyframe {
+                time: i as f32,
+                translation: Vec3::new(i as f32 * 10.0, 5.0, -3.0),
+                rotation: Quat::from_rotation_y(i as f32 * 0.5),
+            })
+            .collect();
+        CameraPath { keyframes }
+    }
+
+    #[test]
+    fn sample_passes_through_keyframes_and_clamps() {
+        assert!(CameraPath::default().sample(0.0).is_none());
+        let path = path();
+        for k in &path.keyframes {
+            let sampled = path.sample(k.time).unwrap();
+            assert!(sampled.translation.abs_diff_eq(k.translation, 1e-4));
+            assert!(sampled.rotation.abs_diff_eq(k.rotation, 1e-4));
+        }
+        // Evenly spaced points on a line stay on it.
+        let mid = path.sample(1.5).unwrap();
+        assert!(
+            mid.translation
+                .abs_diff_eq(Vec3::new(15.0, 5.0, -3.0), 1e-4)
+        );
+        assert!(mid.rotation.abs_diff_eq(Quat::from_rotation_y(0.75), 1e-4));
+
+        let first = &path.keyframes[0];
+        assert_eq!(path.sample(-1.0).unwrap().translation, first.translation);
+        assert!(
+            path.sample(9.0)
+                .unwrap()
+                .translation
+                .abs_diff_eq(Vec3::new(30.0, 5.0, -3.0), 1e-4)
+        );
+
+        let single = CameraPath {
+            keyframes: vec![*first],
+        };
+        assert_eq!(single.sample(5.0).unwrap().translation, first.translation);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let path = path();
+        let loaded = CameraPath::from_text(&path.to_text()).unwrap();
+        assert_eq!(loaded.keyframes.len(), path.keyframes.len());
+        for (a, b) in loaded.keyframes.iter().zip(&path.keyframes) {
+            assert_eq!((a.time, a.translation), (b.time, b.translation));
+            assert!(a.rotation.abs_diff_eq(b.rotation, 1e-6));
+        }
+
+        let commented = format!("# time x y z qx qy qz qw\n\n{}", path.to_text());
+        assert_eq!(
+            CameraPath::from_text(&commented).unwrap().keyframes.len(),
+            4
+        );
+        assert!(CameraPath::from_text("0 1 2 3\n").is_err());
+        assert!(CameraPath::from_text("0 1 2 3 0 0 0 one\n").is_err());
+    }
+}
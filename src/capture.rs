@@ -0,0 +1,327 @@
+use crate::camera_path::{CameraPathPlayer, PathState};
+use crate::config::AppSettings;
+use crate::render::{DisplayImage, RenderResolution};
+use bevy::prelude::*;
+use bevy::render::gpu_readback::{Readback, ReadbackComplete};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::render::renderer::RenderDevice;
+use bevy::tasks::IoTaskPool;
+use bevy::time::TimeUpdateStrategy;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Resource, Clone, Debug)]
+pub struct CaptureSettings {
+    /// Screenshots and sequence folders are written here.
+    pub dir: PathBuf,
+    /// Frames per second of recorded sequences. While recording, every update advances time
+    /// by exactly one frame, however long rendering and saving take.
+    pub sequence_fps: f64,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("captures"),
+            sequence_fps: 60.0,
+        }
+    }
+}
+
+/// Saves the next frame of `out_tex` as a PNG at `path`.
+#[derive(Event, Clone, Debug)]
+pub struct CaptureFrame {
+    pub path: PathBuf,
+}
+
+/// Readback entity of the sequence being recorded.
+#[derive(Resource, Default)]
+pub struct Capture {
+    sequence: Option<Entity>,
+    /// `looping` of the camera path player to restore once a sequence following the path
+    /// stops, `None` if the sequence does not follow a path.
+    path_looping: Option<bool>,
+}
+
+#[derive(Component)]
+pub struct SequenceRecorder {
+    dir: PathBuf,
+    next_frame: u32,
+}
+
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Strips the row padding of a texture readback, forcing alpha to opaque.
+fn unpad_rgba8(data: &[u8], size: UVec2) -> Option<Vec<u8>> {
+    let row = size.x as usize * 4;
+    let padded_row = RenderDevice::align_copy_bytes_per_row(row);
+    if size.x == 0 || size.y == 0 || data.len() < padded_row * (size.y as usize - 1) + row {
+        return None;
+    }
+    let mut pixels = Vec::with_capacity(row * size.y as usize);
+    for y in 0..size.y as usize {
+        pixels.extend_from_slice(&data[y * padded_row..y * padded_row + row]);
+    }
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+    Some(pixels)
+}
+
+/// Box filters `pixels` of `from` down to at most `to` on each axis, so captures rendered
+/// above the window size with `render_scale` come out supersampled at window size.
+fn downsample_rgba8(pixels: Vec<u8>, from: UVec2, to: UVec2) -> (Vec<u8>, UVec2) {
+    let to = to.min(from).max(UVec2::ONE);
+    if to == from {
+        return (pixels, from);
+    }
+    // Source pixels covered by target pixel `i` along an axis of `from` -> `to` pixels.
+    let span = |i: u32, from: u32, to: u32| {
+        let start = i * from / to;
+        start..((i + 1) * from / to).max(start + 1)
+    };
+    let mut out = Vec::with_capacity((to.x * to.y * 4) as usize);
+    for y in 0..to.y {
+        for x in 0..to.x {
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for sy in span(y, from.y, to.y) {
+                for sx in span(x, from.x, to.x) {
+                    let at = ((sy * from.x + sx) * 4) as usize;
+                    for (c, value) in sum.iter_mut().zip(&pixels[at..at + 4]) {
+                        *c += *value as u32;
+                    }
+                    count += 1;
+                }
+            }
+            out.extend(sum.map(|c| ((c + count / 2) / count) as u8));
+        }
+    }
+    (out, to)
+}
+
+/// Encodes and writes the PNG on the IO pool, so recording does not stall the frame.
+/// Frames rendered larger than `window` are downsampled to it.
+fn save_png(data: &[u8], size: UVec2, window: UVec2, path: PathBuf) {
+    let Some(pixels) = unpad_rgba8(data, size) else {
+        println!(
+            "Skipping capture {}: readback does not match {}x{}",
+            path.display(),
+            size.x,
+            size.y
+        );
+        return;
+    };
+    IoTaskPool::get()
+        .spawn(async move {
+            let (pixels, size) = downsample_rgba8(pixels, size, window);
+            if let Some(dir) = path.parent() {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            let image = Image::new(
+                Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    ..default()
+                },
+                TextureDimension::D2,
+                pixels,
+                TextureFormat::Rgba8Unorm,
+                RenderAssetUsages::MAIN_WORLD,
+            );
+            let result = image
+                .try_into_dynamic()
+                .map_err(|err| err.to_string())
+                .and_then(|image| image.save(&path).map_err(|err| err.to_string()));
+            if let Err(err) = result {
+                println!("Failed to save capture {}: {}", path.display(), err);
+            }
+        })
+        .detach();
+}
+
+#[derive(Component)]
+struct ScreenshotPath(PathBuf);
+
+fn save_screenshot(
+    trigger: Trigger<ReadbackComplete>,
+    paths: Query<&ScreenshotPath>,
+    resolution: Res<RenderResolution>,
+    settings: Res<AppSettings>,
+    mut commands: Commands,
+) {
+    if let Ok(ScreenshotPath(path)) = paths.get(trigger.target()) {
+        let window = UVec2::new(settings.width, settings.height);
+        save_png(&trigger.event().0, resolution.0, window, path.clone());
+        println!("Saved screenshot {}", path.display());
+    }
+    commands.entity(trigger.target()).despawn();
+}
+
+fn save_sequence_frame(
+    trigger: Trigger<ReadbackComplete>,
+    mut recorders: Query<&mut SequenceRecorder>,
+    resolution: Res<RenderResolution>,
+    settings: Res<AppSettings>,
+) {
+    let Ok(mut recorder) = recorders.get_mut(trigger.target()) else {
+        return;
+    };
+    let path = recorder
+        .dir
+        .join(format!("frame_{:05}.png", recorder.next_frame));
+    recorder.next_frame += 1;
+    let window = UVec2::new(settings.width, settings.height);
+    save_png(&trigger.event().0, resolution.0, window, path);
+}
+
+fn sequence_dir(settings: &CaptureSettings) -> PathBuf {
+    settings.dir.join(format!("sequence_{}", timestamp()))
+}
+
+pub fn screenshot_path(settings: &CaptureSettings) -> PathBuf {
+    settings.dir.join(format!("screenshot_{}.png", timestamp()))
+}
+
+/// F12 saves a screenshot.
+pub fn screenshot_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<CaptureSettings>,
+    mut captures: EventWriter<CaptureFrame>,
+) {
+    if keyboard.just_pressed(KeyCode::F12) {
+        captures.write(CaptureFrame {
+            path: screenshot_path(&settings),
+        });
+    }
+}
+
+/// F11 starts or stops recording a numbered image sequence. The sequence plays the camera
+/// path once if there is one, and stops at its end.
+pub fn capture_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    settings: Res<CaptureSettings>,
+    display_image: Res<DisplayImage>,
+    mut capture: ResMut<Capture>,
+    mut player: ResMut<CameraPathPlayer>,
+    recorders: Query<&SequenceRecorder>,
+    mut commands: Commands,
+) {
+    let path_ended = capture.path_looping.is_some() && player.state != PathState::Playing;
+    if !keyboard.just_pressed(KeyCode::F11) && !path_ended {
+        return;
+    }
+    if let Some(entity) = capture.sequence.take() {
+        if let Some(looping) = capture.path_looping.take() {
+            player.looping = looping;
+            player.state = PathState::Idle;
+        }
+        if let Ok(recorder) = recorders.get(entity) {
+            println!(
+                "Recorded {} frames to {}",
+                recorder.next_frame,
+                recorder.dir.display()
+            );
+        }
+        commands.entity(entity).despawn();
+        commands.insert_resource(TimeUpdateStrategy::Automatic);
+    } else {
+        let dir = sequence_dir(&settings);
+        println!("Recording sequence to {}", dir.display());
+        if player.play() {
+            capture.path_looping = Some(player.looping);
+            player.looping = false;
+        }
+        let entity = commands
+            .spawn((
+                Readback::texture(display_image.0.clone()),
+                SequenceRecorder { dir, next_frame: 0 },
+            ))
+            .observe(save_sequence_frame)
+            .id();
+        capture.sequence = Some(entity);
+        commands.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / settings.sequence_fps,
+        )));
+    }
+}
+
+/// Reads back the display image for every `CaptureFrame` request.
+pub fn take_captures(
+    mut captures: EventReader<CaptureFrame>,
+    display_image: Res<DisplayImage>,
+    mut commands: Commands,
+) {
+    for capture in captures.read() {
+        commands
+            .spawn((
+                Readback::texture(display_image.0.clone()),
+                ScreenshotPath(capture.path.clone()),
+            ))
+            .observe(save_screenshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supersampled_captures_average_down_to_window_size() {
+        // 4x2 rendered at 2x: each output pixel averages a 2x2 block.
+        let from = UVec2::new(4, 2);
+        let mut pixels = Vec::new();
+        for value in [0u8, 100, 10, 10, 200, 100, 10, 30] {
+            pixels.extend([value, value, value, 255]);
+        }
+        let (out, size) = downsample_rgba8(pixels, from, UVec2::new(2, 1));
+        assert_eq!(size, UVec2::new(2, 1));
+        assert_eq!(out, [100, 100, 100, 255, 15, 15, 15, 255]);
+    }
+
+    #[test]
+    fn captures_at_or_below_window_size_are_kept() {
+        let pixels = vec![7u8; 3 * 2 * 4];
+        let (out, size) = downsample_rgba8(pixels.clone(), UVec2::new(3, 2), UVec2::new(3, 2));
+        assert_eq!((out, size), (pixels.clone(), UVec2::new(3, 2)));
+        let (out, size) = downsample_rgba8(pixels.clone(), UVec2::new(3, 2), UVec2::new(6, 4));
+        assert_eq!((out, size), (pixels, UVec2::new(3, 2)));
+    }
+
+    #[test]
+    fn readback_rows_are_unpadded_and_made_opaque() {
+        let size = UVec2::new(3, 2);
+        let padded_row = RenderDevice::align_copy_bytes_per_row(3 * 4);
+        assert!(padded_row > 3 * 4);
+        let mut data = vec![0xAA; padded_row + 3 * 4];
+        for y in 0..2 {
+            for i in 0..3 * 4 {
+                data[y * padded_row + i] = (y * 100 + i) as u8;
+            }
+        }
+        let pixels = unpad_rgba8(&data, size).unwrap();
+        let mut expected = Vec::new();
+        for y in 0..2 {
+            for x in 0..3 {
+                let base = y * 100 + x * 4;
+                expected.extend([base as u8, base as u8 + 1, base as u8 + 2, 255]);
+            }
+        }
+        assert_eq!(pixels, expected);
+        // The last row does not need its padding.
+        assert!(unpad_rgba8(&data[..padded_row + 3 * 4 - 1], size).is_none());
+    }
+
+    #[test]
+    fn empty_readbacks_are_rejected() {
+        assert!(unpad_rgba8(&[], UVec2::new(3, 0)).is_none());
+        assert!(unpad_rgba8(&[], UVec2::new(0, 3)).is_none());
+        assert!(unpad_rgba8(&[], UVec2::ZERO).is_none());
+    }
+}
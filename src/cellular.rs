@@ -0,0 +1,468 @@
+use crate::config::{PHASE_GRANULAR, PHASE_LIQUID, PHASE_SOLID};
+use crate::history::EditHistory;
+use crate::render::VoxelCamera;
+use crate::structure::StructuralSettings;
+use crate::thermal::ThermalField;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited, in_svo_bounds};
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+const HORIZONTAL: [IVec3; 4] = [IVec3::X, IVec3::Z, IVec3::NEG_X, IVec3::NEG_Z];
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct CellularSettings {
+    /// Simulation steps per second.
+    pub tick_rate: f32,
+    pub max_ticks_per_frame: u32,
+    /// Longest sideways slide of a grain, which bounds how flat a low friction pile gets.
+    pub max_slide: i32,
+    /// How far liquids look sideways for a drop to flow over.
+    pub flow_distance: i32,
+    /// Cells of a liquid body searched for a lower free cell when its top voxel has no drop
+    /// nearby. This is what levels pools wider than `flow_distance`.
+    pub max_pressure_search: usize,
+}
+
+impl Default for CellularSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: 30.0,
+            max_ticks_per_frame: 4,
+            max_slide: 8,
+            flow_distance: 8,
+            max_pressure_search: 1024,
+        }
+    }
+}
+
+/// Bricks (in brick coordinates) that may contain moving voxels. Only these are ticked,
+/// bricks where nothing moved fall asleep until an edit or a neighbour wakes them.
+#[derive(Resource, Default)]
+pub struct ActiveBricks {
+    pub bricks: HashSet<IVec3>,
+    tick: u32,
+}
+
+impl ActiveBricks {
+    /// Wakes the existing bricks touching `min..=max` or one voxel around it.
+    pub fn activate_region(&mut self, world: &VoxelWorld, min: IVec3, max: IVec3) {
+        let (lo, hi): (IVec3, IVec3) = ((min - IVec3::ONE) >> 2, (max + IVec3::ONE) >> 2);
+        for z in lo.z..=hi.z {
+            for y in lo.y..=hi.y {
+                for x in lo.x..=hi.x {
+                    let brick = IVec3::new(x, y, z);
+                    if world.get_brick_at(brick << 2).is_some() {
+                        self.bricks.insert(brick);
+                    }
+                }
+            }
+        }
+    }
+
+    fn wake(&mut self, pos: IVec3) {
+        self.bricks.insert(pos >> 2);
+        for offset in [IVec3::X, IVec3::Y, IVec3::Z] {
+            self.bricks.insert((pos + offset) >> 2);
+            self.bricks.insert((pos - offset) >> 2);
+        }
+    }
+}
+
+/// Free cells below `pos`, counting at most `limit`.
+fn drop_depth(blocked: impl Fn(IVec3) -> bool, pos: IVec3, limit: i32) -> i32 {
+    (1..=limit)
+        .find(|&depth| blocked(pos - IVec3::Y * depth))
+        .map_or(limit, |depth| depth - 1)
+}
+
+/// Lowest free cell below the level of `pos` that touches the liquid body `pos` is the top
+/// of. Moving there is what a pressure difference would do: water stacked on a plateau
+/// flows out to the edge of its pool, however wide the pool is.
+fn pressure_target(
+    world: &VoxelWorld,
+    blocked: impl Fn(IVec3) -> bool,
+    pos: IVec3,
+    mat_id: u8,
+    max_search: usize,
+) -> Option<IVec3> {
+    let mut visited: HashSet<IVec3> = HashSet::from_iter([pos]);
+    let mut queue = VecDeque::from([pos]);
+    let mut best: Option<IVec3> = None;
+    while let Some(cell) = queue.pop_front() {
+        for offset in [
+            IVec3::NEG_Y,
+            IVec3::X,
+            IVec3::Z,
+            IVec3::NEG_X,
+            IVec3::NEG_Z,
+            IVec3::Y,
+        ] {
+            let next = cell + offset;
+            if next.y > pos.y || visited.len() >= max_search || !visited.insert(next) {
+                continue;
+            }
+            if world.get_voxel(next) == mat_id {
+                queue.push_back(next);
+            } else if next.y < pos.y && !blocked(next) && best.is_none_or(|b| next.y < b.y) {
+                best = Some(next);
+            }
+        }
+    }
+    best
+}
+
+/// Where the mobile voxel at `pos` moves this tick, if anywhere.
+fn find_move(
+    world: &VoxelWorld,
+    pos: IVec3,
+    mat_id: u8,
+    dirs: [IVec3; 4],
+    settings: &CellularSettings,
+    ground_level: i32,
+) -> Option<IVec3> {
+    // Nothing moves out of the part of the world the SVO can address.
+    let blocked = |p: IVec3| p.y < ground_level || !in_svo_bounds(p) || world.is_solid(p);
+    let material = world.material(mat_id);
+    let below = pos + IVec3::NEG_Y;
+    if !blocked(below) {
+        return Some(below);
+    }
+    // Grains sink through liquids.
+    if material.phase == PHASE_GRANULAR
+        && below.y >= ground_level
+        && world.material(world.get_voxel(below)).phase == PHASE_LIQUID
+    {
+        return Some(below);
+    }
+
+    // A grain slides towards a drop once the slope to it (drop over run) is steeper than its
+    // friction, `tan(angle of repose) = friction`. Liquids flow over any drop.
+    let (reach, slope) = if material.phase == PHASE_LIQUID {
+        (settings.flow_distance, 0.0)
+    } else {
+        (settings.max_slide, material.friction.max(0.0))
+    };
+    // Head for the nearest such drop, falling diagonally if it is right next to us.
+    let mut best: Option<(i32, IVec3)> = None;
+    for dir in dirs {
+        for step in 1..=reach.min(best.map_or(reach, |(s, _)| s - 1)) {
+            let side = pos + dir * step;
+            if blocked(side) {
+                break;
+            }
+            let needed = (slope * step as f32).floor() as i32 + 1;
+            if drop_depth(blocked, side, needed) >= needed {
+                best = Some((step, dir));
+                break;
+            }
+        }
+    }
+    if let Some((step, dir)) = best {
+        return Some(if step == 1 {
+            pos + dir + IVec3::NEG_Y
+        } else {
+            pos + dir
+        });
+    }
+    // Only the top of a liquid column moves by pressure, the rest follows by falling.
+    if material.phase == PHASE_LIQUID && world.get_voxel(pos + IVec3::Y) != mat_id {
+        return pressure_target(world, blocked, pos, mat_id, settings.max_pressure_search);
+    }
+    None
+}
+
+/// Advances the simulation one step over the active bricks, bottom up. Returns whether
+/// anything moved.
+fn tick(
+    world: &mut VoxelWorld,
+    active: &mut ActiveBricks,
+    mut thermal: Option<&mut ThermalField>,
+    settings: &CellularSettings,
+    ground_level: i32,
+) -> bool {
+    let mut bricks: Vec<IVec3> = active.bricks.drain().collect();
+    bricks.sort_by_key(|b| (b.y, b.z, b.x));
+    active.tick = active.tick.wrapping_add(1);
+    // Rotate the preferred direction every tick so piles and puddles grow evenly.
+    let mut dirs = HORIZONTAL;
+    dirs.rotate_left(active.tick as usize % 4);
+
+    let mut moved: HashSet<IVec3> = HashSet::default();
+    let mut changed = false;
+    for brick_pos in bricks {
+        let origin = brick_pos << 2;
+        let Some(brick) = world.get_brick_at(origin).copied() else {
+            continue;
+        };
+        // Brick layout is y major, so index order already walks bottom up.
+        for i in 0..64 {
+            let mat_id = brick.voxels[i];
+            let local = IVec3::new(i as i32 & 3, (i as i32 >> 4) & 3, (i as i32 >> 2) & 3);
+            let pos = origin + local;
+            if mat_id == 0
+                || world.material(mat_id).phase == PHASE_SOLID
+                || moved.contains(&pos)
+                || world.get_voxel(pos) != mat_id
+            {
+                continue;
+            }
+            let Some(target) = find_move(world, pos, mat_id, dirs, settings, ground_level) else {
+                continue;
+            };
+
+            let displaced = world.set_voxel(target, mat_id);
+            world.set_voxel(pos, displaced);
+            if let Some(field) = thermal.as_deref_mut() {
+                field.swap(pos, target);
+            }
+            moved.insert(target);
+            active.wake(pos);
+            active.wake(target);
+            changed = true;
+        }
+    }
+    changed
+}
+
+pub fn activate_edited_bricks(
+    mut edits: EventReader<VoxelsEdited>,
+    world: Res<VoxelWorld>,
+    mut active: ResMut<ActiveBricks>,
+) {
+    for edit in edits.read() {
+        active.activate_region(&world, edit.min, edit.max);
+    }
+}
+
+pub fn simulate_cellular(
+    time: Res<Time>,
+    mut world: ResMut<VoxelWorld>,
+    mut active: ResMut<ActiveBricks>,
+    mut thermal: Option<ResMut<ThermalField>>,
+    settings: Res<CellularSettings>,
+    structural: Res<StructuralSettings>,
+    mut accumulator: Local<f32>,
+) {
+    if active.bricks.is_empty() {
+        *accumulator = 0.0;
+        return;
+    }
+    let step = 1.0 / settings.tick_rate;
+    *accumulator =
+        (*accumulator + time.delta_secs()).min(step * settings.max_ticks_per_frame as f32);
+
+    let mut changed = false;
+    while *accumulator >= step && !active.bricks.is_empty() {
+        *accumulator -= step;
+        changed |= tick(
+            world.bypass_change_detection(),
+            &mut active,
+            thermal.as_deref_mut(),
+            &settings,
+            structural.ground_level,
+        );
+    }
+    // Only touch change detection when something moved, so idle frames skip the SVO rebuild.
+    if changed {
+        world.set_changed();
+    }
+}
+
+/// G pours sand, shift + G pours water, just in front of the surface under the crosshair.
+pub fn pour_at_cursor(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    mut edits: EventWriter<VoxelsEdited>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyG) {
+        return;
+    }
+    let Ok(transform) = camera_q.single() else {
+        return;
+    };
+    let Some(hit) = world.raycast(transform.translation, *transform.forward(), 1024.0) else {
+        return;
+    };
+    let phase = if keyboard.pressed(KeyCode::ShiftLeft) {
+        PHASE_LIQUID
+    } else {
+        PHASE_GRANULAR
+    };
+    let Some(mat_id) = world.palette.iter().position(|m| m.phase == phase) else {
+        return;
+    };
+
+    let center = hit.pos + hit.normal.as_ivec3() * 3;
+    history.begin(&world, "Pour");
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let pos = center + IVec3::new(x, y, z);
+                if !world.is_solid(pos) {
+                    history.set_voxel(&mut world, pos, mat_id as u8);
+                }
+            }
+        }
+    }
+    history.commit(&world);
+    edits.write(VoxelsEdited {
+        min: center - IVec3::ONE,
+        max: center + IVec3::ONE,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Material;
+
+    const STONE: u8 = 1;
+    const SAND: u8 = 2;
+    const WATER: u8 = 3;
+
+    fn world(sand_friction: f32) -> VoxelWorld {
+        let material = |phase, friction| Material {
+            phase,
+            friction,
+            ..default()
+        };
+        VoxelWorld {
+            palette: vec![
+                Material::default(),
+                material(PHASE_SOLID, 1.0),
+                material(PHASE_GRANULAR, sand_friction),
+                material(PHASE_LIQUID, 0.0),
+            ],
+            ..default()
+        }
+    }
+
+    /// Ticks until nothing moves, with the ground at y = 0.
+    fn settle(world: &mut VoxelWorld, settings: &CellularSettings, min: IVec3, max: IVec3) {
+        let mut active = ActiveBricks::default();
+        active.activate_region(world, min, max);
+        for _ in 0..2000 {
+            if !tick(world, &mut active, None, settings, 0) {
+                return;
+            }
+        }
+        panic!("still moving after 2000 ticks");
+    }
+
+    fn find(world: &VoxelWorld, mat_id: u8, min: IVec3, max: IVec3) -> Vec<IVec3> {
+        let mut found = Vec::new();
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let pos = IVec3::new(x, y, z);
+                    if world.get_voxel(pos) == mat_id {
+                        found.push(pos);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    fn pile_height(friction: f32) -> i32 {
+        let mut world = world(friction);
+        let base = IVec3::new(100, 0, 100);
+        for y in 0..24 {
+            world.set_voxel(base + IVec3::Y * (y + 4), SAND);
+        }
+        settle(
+            &mut world,
+            &CellularSettings::default(),
+            base,
+            base + IVec3::Y * 28,
+        );
+        let sand = find(
+            &world,
+            SAND,
+            base - IVec3::new(12, 0, 12),
+            base + IVec3::new(12, 28, 12),
+        );
+        assert_eq!(sand.len(), 24);
+        for pos in &sand {
+            assert!(
+                pos.y == 0 || world.is_solid(*pos - IVec3::Y),
+                "{pos} is floating"
+            );
+        }
+        sand.iter().map(|p| p.y).max().unwrap()
+    }
+
+    #[test]
+    fn sand_falls_and_piles_steeper_with_more_friction() {
+        let heights: Vec<i32> = [0.1, 0.5, 1.0, 2.0].into_iter().map(pile_height).collect();
+        assert_eq!(heights[0], 0, "low friction sand spreads flat");
+        assert!(heights.windows(2).all(|h| h[0] < h[1]), "{heights:?}");
+    }
+
+    #[test]
+    fn water_levels_out_wider_than_its_flow_distance() {
+        let mut world = world(1.0);
+        let base = IVec3::new(100, 0, 100);
+        for z in -16..=16 {
+            for x in -16..=16 {
+                world.set_voxel(base + IVec3::new(x, 0, z), STONE);
+            }
+        }
+        for y in 1..=25 {
+            world.set_voxel(base + IVec3::Y * y, WATER);
+        }
+        let settings = CellularSettings {
+            flow_distance: 1,
+            ..default()
+        };
+        settle(&mut world, &settings, base, base + IVec3::Y * 25);
+        let water = find(
+            &world,
+            WATER,
+            base - IVec3::new(16, 0, 16),
+            base + IVec3::new(16, 25, 16),
+        );
+        assert_eq!(water.len(), 25);
+        assert!(water.iter().all(|p| p.y == 1), "{water:?}");
+    }
+
+    #[test]
+    fn only_active_bricks_tick() {
+        let mut world = world(1.0);
+        let (awake, asleep) = (IVec3::new(8, 9, 8), IVec3::new(40, 9, 40));
+        world.set_voxel(awake, SAND);
+        world.set_voxel(asleep, SAND);
+        let mut active = ActiveBricks::default();
+        active.activate_region(&world, awake, awake);
+        assert!(tick(
+            &mut world,
+            &mut active,
+            None,
+            &CellularSettings::default(),
+            0
+        ));
+        assert_eq!(world.get_voxel(awake - IVec3::Y), SAND);
+        assert_eq!(world.get_voxel(asleep), SAND);
+        assert!(!active.bricks.contains(&(asleep >> 2)));
+    }
+
+    #[test]
+    fn grains_stay_inside_the_svo() {
+        let mut world = world(0.1);
+        for y in 0..16 {
+            world.set_voxel(IVec3::new(0, y + 2, 0), SAND);
+        }
+        settle(
+            &mut world,
+            &CellularSettings::default(),
+            IVec3::ZERO,
+            IVec3::splat(18),
+        );
+        let sand = find(&world, SAND, IVec3::splat(-12), IVec3::splat(18));
+        assert_eq!(sand.len(), 16);
+        assert!(sand.iter().all(|p| in_svo_bounds(*p)), "{sand:?}");
+    }
+}
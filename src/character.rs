@@ -0,0 +1,505 @@
+use crate::render::VoxelCamera;
+use crate::structure::StructuralSettings;
+use crate::voxel_map::VoxelWorld;
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Resource, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    Fly,
+    Walk,
+}
+
+/// Fly camera controls.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// Fly speed in voxels per second.
+    pub speed: f32,
+    /// Radians per pixel of mouse motion.
+    pub sensitivity: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            speed: 100.0,
+            sensitivity: 0.002,
+        }
+    }
+}
+
+/// Walking body attached to the `VoxelCamera`. Sizes are in voxels, the camera sits at
+/// `eye_height` above the body center.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CharacterController {
+    pub half_extents: Vec3,
+    pub eye_height: f32,
+    pub walk_speed: f32,
+    pub run_multiplier: f32,
+    pub jump_speed: f32,
+    /// Tallest ledge that is climbed without jumping.
+    pub step_height: f32,
+    /// Horizontal acceleration on ground with `Material.friction` of 0.5.
+    pub ground_acceleration: f32,
+    pub air_acceleration: f32,
+    pub velocity: Vec3,
+    pub grounded: bool,
+}
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        Self {
+            half_extents: Vec3::new(3.0, 8.5, 3.0),
+            eye_height: 7.0,
+            walk_speed: 14.0,
+            run_multiplier: 2.0,
+            jump_speed: 48.0,
+            step_height: 1.0,
+            ground_acceleration: 120.0,
+            air_acceleration: 20.0,
+            velocity: Vec3::ZERO,
+            grounded: false,
+        }
+    }
+}
+
+/// True if the box touches any solid voxel, everything below `ground_level` counts as solid.
+fn overlaps_solid(world: &VoxelWorld, min: Vec3, max: Vec3, ground_level: i32) -> bool {
+    min.y < ground_level as f32 || world.overlaps_aabb(min, max)
+}
+
+/// Moves the box along one axis, stopping flush against the first solid voxel.
+/// Returns the distance actually travelled.
+fn move_axis(
+    world: &VoxelWorld,
+    center: &mut Vec3,
+    half_extents: Vec3,
+    axis: usize,
+    delta: f32,
+    ground_level: i32,
+) -> f32 {
+    const SKIN: f32 = 1e-3;
+    let mut motion = Vec3::ZERO;
+    motion[axis] = delta;
+    let mut travelled =
+        match world.sweep_aabb(*center - half_extents, *center + half_extents, motion) {
+            Some(hit) => delta * hit.toi - SKIN * delta.signum(),
+            None => delta,
+        };
+    if axis == 1 && delta < 0.0 {
+        let floor = ground_level as f32 + half_extents.y;
+        travelled = travelled.max((floor - center.y).min(0.0));
+    }
+    // Never move backwards out of an already overlapping box.
+    if travelled * delta.signum() < 0.0 {
+        travelled = 0.0;
+    }
+    center[axis] += travelled;
+    travelled
+}
+
+fn ground_friction(world: &VoxelWorld, center: Vec3, half_extents: Vec3) -> f32 {
+    let below = (center - Vec3::Y * (half_extents.y + 0.5))
+        .floor()
+        .as_ivec3();
+    let mat_id = world.get_voxel(below);
+    if mat_id == 0 {
+        0.5
+    } else {
+        world.material(mat_id).friction
+    }
+}
+
+pub fn toggle_camera_mode(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut mode: ResMut<CameraMode>,
+    camera_q: Query<Entity, With<VoxelCamera>>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyF) {
+        return;
+    }
+    *mode = match *mode {
+        CameraMode::Fly => CameraMode::Walk,
+        CameraMode::Walk => CameraMode::Fly,
+    };
+    if let Ok(camera) = camera_q.single() {
+        if *mode == CameraMode::Walk {
+            commands
+                .entity(camera)
+                .insert(CharacterController::default());
+        } else {
+            commands.entity(camera).remove::<CharacterController>();
+        }
+    }
+    println!("Camera mode: {:?}", *mode);
+}
+
+pub fn character_controller_system(
+    time: Res<Time>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    world: Res<VoxelWorld>,
+    structural: Res<StructuralSettings>,
+    mut camera_q: Query<(&mut Transform, &mut CharacterController), With<VoxelCamera>>,
+) {
+    let Ok((mut transform, mut controller)) = camera_q.single_mut() else {
+        return;
+    };
+    let forward = transform.forward().with_y(0.0).normalize_or_zero();
+    let right = transform.right().with_y(0.0).normalize_or_zero();
+    let mut wish = Vec3::ZERO;
+    if keyboard.pressed(KeyCode::KeyW) {
+        wish += forward;
+    }
+    if keyboard.pressed(KeyCode::KeyS) {
+        wish -= forward;
+    }
+    if keyboard.pressed(KeyCode::KeyD) {
+        wish += right;
+    }
+    if keyboard.pressed(KeyCode::KeyA) {
+        wish -= right;
+    }
+    let mut speed = controller.walk_speed;
+    if keyboard.pressed(KeyCode::ShiftLeft) {
+        speed *= controller.run_multiplier;
+    }
+    let target = wish.normalize_or_zero() * speed;
+    let jump = keyboard.just_pressed(KeyCode::Space);
+
+    let center = transform.translation - Vec3::Y * controller.eye_height;
+    let dt = time.delta_secs().min(1.0 / 20.0);
+    let center = step_character(
+        &mut controller,
+        &world,
+        &structural,
+        center,
+        target,
+        jump,
+        dt,
+    );
+    transform.translation = center + Vec3::Y * controller.eye_height;
+}
+
+/// Advances the body centered at `center` by `dt` towards the horizontal velocity `target`,
+/// colliding with the world and climbing ledges up to `step_height`. Returns the new center.
+fn step_character(
+    controller: &mut CharacterController,
+    world: &VoxelWorld,
+    structural: &StructuralSettings,
+    mut center: Vec3,
+    target: Vec3,
+    jump: bool,
+    dt: f32,
+) -> Vec3 {
+    let gravity = structural.gravity / structural.voxel_size;
+    let half = controller.half_extents;
+    let ground = structural.ground_level;
+
+    // Spawned inside terrain (e.g. when switching from fly mode), climb out first.
+    let mut unstuck = 0;
+    while unstuck < 256 && overlaps_solid(world, center - half, center + half, ground) {
+        center.y = center.y.floor() + 1.0;
+        unstuck += 1;
+    }
+
+    let acceleration = if controller.grounded {
+        controller.ground_acceleration * ground_friction(world, center, half) / 0.5
+    } else {
+        controller.air_acceleration
+    };
+    let horizontal = controller.velocity.with_y(0.0);
+    let horizontal = horizontal.move_towards(target, acceleration * dt);
+    controller.velocity = horizontal.with_y(controller.velocity.y - gravity * dt);
+
+    if controller.grounded && jump {
+        controller.velocity.y = controller.jump_speed;
+    }
+
+    let delta = controller.velocity * dt;
+    let was_grounded = controller.grounded;
+
+    for axis in [0, 2] {
+        if delta[axis] == 0.0 {
+            continue;
+        }
+        let before = center;
+        let moved = move_axis(world, &mut center, half, axis, delta[axis], ground);
+        if moved != delta[axis] && was_grounded {
+            // Try the same move from one step higher, to walk up single voxel ledges.
+            let mut raised = before;
+            if move_axis(world, &mut raised, half, 1, controller.step_height, ground)
+                == controller.step_height
+                && move_axis(world, &mut raised, half, axis, delta[axis], ground) == delta[axis]
+            {
+                center = raised;
+                continue;
+            }
+        }
+        if moved != delta[axis] {
+            controller.velocity[axis] = 0.0;
+        }
+    }
+
+    let moved_y = move_axis(world, &mut center, half, 1, delta.y, ground);
+    controller.grounded = delta.y < 0.0 && moved_y > delta.y;
+    if moved_y != delta.y {
+        controller.velocity.y = 0.0;
+    }
+    center
+}
+
+pub fn camera_movement_system(
+    time: Res<Time>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    camera_mode: Res<CameraMode>,
+    camera_settings: Res<CameraSettings>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut camera_q: Query<&mut Transform, With<VoxelCamera>>,
+) {
+    let Ok(mut transform) = camera_q.single_mut() else {
+        return;
+    };
+
+    let mut rotation_move = Vec2::ZERO;
+    for event in mouse_motion.read() {
+        rotation_move += event.delta;
+    }
+
+    if rotation_move.length_squared() > 0.0 {
+        let sensitivity = camera_settings.sensitivity;
+        let (mut yaw, mut pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+
+        yaw -= rotation_move.x * sensitivity;
+        // Mouse y grows downwards, moving the mouse up pitches the camera up.
+        pitch -= rotation_move.y * sensitivity;
+        pitch = pitch.clamp(-1.5, 1.5);
+
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0);
+    }
+
+    // In walk mode the character controller owns the translation.
+    if *camera_mode != CameraMode::Fly {
+        return;
+    }
+
+    let mut velocity = Vec3::ZERO;
+    let local_z = transform.forward();
+    let local_x = transform.right();
+
+    if keyboard.pressed(KeyCode::KeyW) {
+        velocity += *local_z;
+    }
+    if keyboard.pressed(KeyCode::KeyS) {
+        velocity -= *local_z;
+    }
+    if keyboard.pressed(KeyCode::KeyD) {
+        velocity += *local_x;
+    }
+    if keyboard.pressed(KeyCode::KeyA) {
+        velocity -= *local_x;
+    }
+
+    transform.translation +=
+        velocity.normalize_or_zero() * camera_settings.speed * time.delta_secs();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Material;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn world(voxels: impl IntoIterator<Item = (IVec3, u8)>) -> VoxelWorld {
+        let mut world = VoxelWorld {
+            palette: vec![
+                Material::default(),
+                Material::default(),
+                Material {
+                    friction: 0.1,
+                    ..default()
+                },
+                Material {
+                    friction: 1.0,
+                    ..default()
+                },
+            ],
+            ..default()
+        };
+        for (pos, mat_id) in voxels {
+            world.set_voxel(pos, mat_id);
+        }
+        world
+    }
+
+    /// `frames` steps of `DT` towards `target`, returns the final center.
+    fn run(
+        controller: &mut CharacterController,
+        world: &VoxelWorld,
+        mut center: Vec3,
+        target: Vec3,
+        frames: usize,
+    ) -> Vec3 {
+        let structural = StructuralSettings::default();
+        for _ in 0..frames {
+            center = step_character(controller, world, &structural, center, target, false, DT);
+        }
+        center
+    }
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn move_axis_stops_flush_against_voxels() {
+        let world = world([(IVec3::new(5, 0, 0), 1)]);
+        let half = Vec3::splat(0.5);
+        let mut center = Vec3::new(2.5, 0.5, 0.5);
+        let moved = move_axis(&world, &mut center, half, 0, 10.0, -100);
+        assert_close(center.x, 4.5, 0.01);
+        assert_close(moved, 2.0, 0.01);
+        // Sliding past the voxel on another row is not blocked.
+        let mut center = Vec3::new(2.5, 1.5, 0.5);
+        assert_eq!(move_axis(&world, &mut center, half, 0, 10.0, -100), 10.0);
+        // Falling stops on the ground level.
+        let mut center = Vec3::new(0.5, 3.0, 0.5);
+        move_axis(&world, &mut center, half, 1, -10.0, 0);
+        assert_close(center.y, 0.5, 1e-4);
+    }
+
+    #[test]
+    fn falling_body_lands_on_the_ground() {
+        let mut controller = CharacterController::default();
+        let center = run(
+            &mut controller,
+            &world([]),
+            Vec3::new(0.0, 30.0, 0.0),
+            Vec3::ZERO,
+            90,
+        );
+        assert!(controller.grounded);
+        assert_eq!(controller.velocity.y, 0.0);
+        assert_close(center.y, controller.half_extents.y, 0.01);
+    }
+
+    #[test]
+    fn jump_reaches_the_expected_height_and_lands() {
+        let world = world([]);
+        let structural = StructuralSettings::default();
+        let mut controller = CharacterController::default();
+        let rest = run(
+            &mut controller,
+            &world,
+            Vec3::new(0.0, 8.5, 0.0),
+            Vec3::ZERO,
+            5,
+        );
+        assert!(controller.grounded);
+
+        let mut center = step_character(
+            &mut controller,
+            &world,
+            &structural,
+            rest,
+            Vec3::ZERO,
+            true,
+            DT,
+        );
+        assert!(!controller.grounded);
+        let mut peak = center.y;
+        for _ in 0..120 {
+            center = run(&mut controller, &world, center, Vec3::ZERO, 1);
+            peak = peak.max(center.y);
+        }
+        let gravity = structural.gravity / structural.voxel_size;
+        let expected = controller.jump_speed.powi(2) / (2.0 * gravity);
+        assert_close(peak - rest.y, expected, 0.5);
+        assert!(controller.grounded);
+        assert_close(center.y, rest.y, 0.01);
+    }
+
+    #[test]
+    fn walking_steps_up_a_single_voxel_ledge() {
+        let step = (10..40).flat_map(|x| (-6..6).map(move |z| (IVec3::new(x, 0, z), 1)));
+        let world = world(step);
+        let mut controller = CharacterController::default();
+        let target = Vec3::X * controller.walk_speed;
+        let center = run(
+            &mut controller,
+            &world,
+            Vec3::new(0.0, 8.5, 0.0),
+            target,
+            150,
+        );
+        assert!(center.x > 15.0, "stuck at the ledge at {}", center);
+        assert!(controller.grounded);
+        assert_close(center.y, 9.5, 0.01);
+    }
+
+    #[test]
+    fn two_voxel_ledges_block_walking() {
+        let wall = (10..40)
+            .flat_map(|x| (-6..6).flat_map(move |z| [0, 1].map(|y| (IVec3::new(x, y, z), 1))));
+        let world = world(wall);
+        let mut controller = CharacterController::default();
+        let target = Vec3::X * controller.walk_speed;
+        let center = run(
+            &mut controller,
+            &world,
+            Vec3::new(0.0, 8.5, 0.0),
+            target,
+            150,
+        );
+        assert_close(center.x, 10.0 - controller.half_extents.x, 0.01);
+        assert_close(center.y, 8.5, 0.01);
+    }
+
+    #[test]
+    fn ground_acceleration_scales_with_friction() {
+        let floor = |mat_id| {
+            world((-8..8).flat_map(move |x| (-8..8).map(move |z| (IVec3::new(x, -1, z), mat_id))))
+        };
+        let mut speeds = Vec::new();
+        for mat_id in [2, 3] {
+            let world = floor(mat_id);
+            let mut controller = CharacterController {
+                grounded: true,
+                ..default()
+            };
+            let center = Vec3::new(0.0, 8.5, 0.0);
+            let structural = StructuralSettings {
+                ground_level: -100,
+                ..default()
+            };
+            let target = Vec3::X * controller.walk_speed;
+            step_character(
+                &mut controller,
+                &world,
+                &structural,
+                center,
+                target,
+                false,
+                DT,
+            );
+            speeds.push(controller.velocity.x);
+        }
+        let expected = CharacterController::default().ground_acceleration * DT / 0.5;
+        assert_close(speeds[0], expected * 0.1, 1e-4);
+        assert_close(speeds[1], expected * 1.0, 1e-4);
+        // Without a voxel below, as on the ground level, friction is 0.5.
+        let half = Vec3::new(3.0, 8.5, 3.0);
+        assert_eq!(
+            ground_friction(&world([]), Vec3::new(0.0, 8.5, 0.0), half),
+            0.5
+        );
+        assert_eq!(
+            ground_friction(&floor(2), Vec3::new(0.0, 8.5, 0.0), half),
+            0.1
+        );
+    }
+}
@@ -0,0 +1,631 @@
+use crate::voxel_map::{VoxelWorld, brick_index};
+use bevy::math::{IVec3, Vec3};
+
+/// A solid voxel touched by a shape query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelContact {
+    pub pos: IVec3,
+    pub material: u8,
+    /// Direction that pushes the query shape out of the voxel.
+    pub normal: Vec3,
+    pub depth: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepHit {
+    /// Fraction of the motion travelled before touching, in `0.0..=1.0`.
+    pub toi: f32,
+    pub pos: IVec3,
+    pub material: u8,
+    pub normal: Vec3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub pos: IVec3,
+    pub material: u8,
+    /// Normal of the face the ray entered through, zero if it started inside the voxel.
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+/// Occupancy bits of the voxels in `lo..=hi` (brick local), in `Brick` layout.
+fn brick_range_mask(lo: IVec3, hi: IVec3) -> u64 {
+    if lo == IVec3::ZERO && hi == IVec3::splat(3) {
+        return u64::MAX;
+    }
+    let mut mask = 0u64;
+    for y in lo.y..=hi.y {
+        for z in lo.z..=hi.z {
+            for x in lo.x..=hi.x {
+                mask |= 1 << (x + z * 4 + y * 16);
+            }
+        }
+    }
+    mask
+}
+
+/// Axis along which `a` pushes out of `b` the least, and that depth.
+fn min_penetration(a_min: Vec3, a_max: Vec3, b_min: Vec3, b_max: Vec3) -> (Vec3, f32) {
+    let mut best = (Vec3::Y, f32::MAX);
+    for axis in 0..3 {
+        let push_pos = b_max[axis] - a_min[axis];
+        let push_neg = a_max[axis] - b_min[axis];
+        let mut normal = Vec3::ZERO;
+        if push_pos < push_neg {
+            normal[axis] = 1.0;
+            if push_pos < best.1 {
+                best = (normal, push_pos);
+            }
+        } else {
+            normal[axis] = -1.0;
+            if push_neg < best.1 {
+                best = (normal, push_neg);
+            }
+        }
+    }
+    best
+}
+
+impl VoxelWorld {
+    /// Calls `f` for every solid voxel in `min..=max`, skipping empty space level by level the
+    /// way the SVO does: only existing sectors are visited, in them only the 16^3 cells set in
+    /// `Sector::occupancy`, then only existing bricks, filtered with their occupancy mask.
+    ///
+    /// This reads the sector and brick maps rather than `SvoStorage`, which is only rebuilt in
+    /// `VoxelSet::Rebuild` and would miss edits made earlier in the frame.
+    pub fn for_each_solid_in(&self, min: IVec3, max: IVec3, mut f: impl FnMut(IVec3, u8)) {
+        if min.cmpgt(max).any() {
+            return;
+        }
+        let (sector_min, sector_max): (IVec3, IVec3) = (min >> 6, max >> 6);
+        let span = (sector_max - sector_min + 1).as_i64vec3();
+        if span.x * span.y * span.z > self.sectors.len() as i64 {
+            // Fewer sectors exist than the range covers, visit those instead, in range order.
+            let mut sectors: Vec<IVec3> = self
+                .sectors
+                .keys()
+                .copied()
+                .filter(|s| s.cmpge(sector_min).all() && s.cmple(sector_max).all())
+                .collect();
+            sectors.sort_by_key(|s| [s.z, s.y, s.x]);
+            for sector_pos in sectors {
+                self.for_each_solid_in_sector(sector_pos, min, max, &mut f);
+            }
+            return;
+        }
+        for sz in sector_min.z..=sector_max.z {
+            for sy in sector_min.y..=sector_max.y {
+                for sx in sector_min.x..=sector_max.x {
+                    self.for_each_solid_in_sector(IVec3::new(sx, sy, sz), min, max, &mut f);
+                }
+            }
+        }
+    }
+
+    fn for_each_solid_in_sector(
+        &self,
+        sector_pos: IVec3,
+        min: IVec3,
+        max: IVec3,
+        f: &mut impl FnMut(IVec3, u8),
+    ) {
+        let Some(sector) = self.sectors.get(&sector_pos) else {
+            return;
+        };
+        let origin = sector_pos << 6;
+        let lo = min.max(origin);
+        let hi = max.min(origin + 63);
+        let (cell_min, cell_max): (IVec3, IVec3) = ((lo - origin) >> 4, (hi - origin) >> 4);
+        let occupancy = sector.occupancy();
+
+        for cz in cell_min.z..=cell_max.z {
+            for cy in cell_min.y..=cell_max.y {
+                for cx in cell_min.x..=cell_max.x {
+                    if occupancy & 1 << (cx + cz * 4 + cy * 16) == 0 {
+                        continue;
+                    }
+                    let cell_origin = origin + (IVec3::new(cx, cy, cz) << 4);
+                    let cell_lo = lo.max(cell_origin);
+                    let cell_hi = hi.min(cell_origin + 15);
+                    let (brick_min, brick_max): (IVec3, IVec3) = (cell_lo >> 2, cell_hi >> 2);
+
+                    for bz in brick_min.z..=brick_max.z {
+                        for by in brick_min.y..=brick_max.y {
+                            for bx in brick_min.x..=brick_max.x {
+                                let brick_origin = IVec3::new(bx, by, bz) << 2;
+                                let Some(brick) = sector.bricks.get(&brick_index(brick_origin))
+                                else {
+                                    continue;
+                                };
+                                let local_lo = cell_lo.max(brick_origin) - brick_origin;
+                                let local_hi = cell_hi.min(brick_origin + 3) - brick_origin;
+                                let mut mask =
+                                    brick.pack_bits_64() & brick_range_mask(local_lo, local_hi);
+                                while mask != 0 {
+                                    let i = mask.trailing_zeros() as i32;
+                                    mask &= mask - 1;
+                                    let local = IVec3::new(i & 3, (i >> 4) & 3, (i >> 2) & 3);
+                                    f(brick_origin + local, brick.voxels[i as usize]);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Solid voxels overlapping the box, with the normal pushing the box out of each one.
+    pub fn overlap_aabb(&self, min: Vec3, max: Vec3) -> Vec<VoxelContact> {
+        let mut contacts = Vec::new();
+        let lo = min.floor().as_ivec3();
+        let hi = max.ceil().as_ivec3() - IVec3::ONE;
+        self.for_each_solid_in(lo, hi, |pos, material| {
+            let (normal, depth) = min_penetration(min, max, pos.as_vec3(), pos.as_vec3() + 1.0);
+            contacts.push(VoxelContact {
+                pos,
+                material,
+                normal,
+                depth,
+            });
+        });
+        contacts
+    }
+
+    pub fn overlaps_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        let lo = min.floor().as_ivec3();
+        let hi = max.ceil().as_ivec3() - IVec3::ONE;
+        let mut hit = false;
+        self.for_each_solid_in(lo, hi, |_, _| hit = true);
+        hit
+    }
+
+    /// Solid voxels intersecting the sphere, normals point from the voxel to the center.
+    pub fn overlap_sphere(&self, center: Vec3, radius: f32) -> Vec<VoxelContact> {
+        let mut contacts = Vec::new();
+        let lo = (center - radius).floor().as_ivec3();
+        let hi = (center + radius).ceil().as_ivec3() - IVec3::ONE;
+        self.for_each_solid_in(lo, hi, |pos, material| {
+            let voxel_min = pos.as_vec3();
+            let closest = center.clamp(voxel_min, voxel_min + 1.0);
+            let offset = center - closest;
+            let distance = offset.length();
+            if distance > radius {
+                return;
+            }
+            let (normal, depth) = if distance > 1e-6 {
+                (offset / distance, radius - distance)
+            } else {
+                // Center inside the voxel, push out through the nearest face.
+                let (normal, depth) =
+                    min_penetration(center - radius, center + radius, voxel_min, voxel_min + 1.0);
+                (normal, depth)
+            };
+            contacts.push(VoxelContact {
+                pos,
+                material,
+                normal,
+                depth,
+            });
+        });
+        contacts
+    }
+
+    /// Earliest time of impact of a box moving by `motion`. Voxels the box already overlaps
+    /// only count if the motion goes further into them.
+    pub fn sweep_aabb(&self, min: Vec3, max: Vec3, motion: Vec3) -> Option<SweepHit> {
+        let lo = min.min(min + motion).floor().as_ivec3();
+        let hi = max.max(max + motion).ceil().as_ivec3() - IVec3::ONE;
+        let mut best: Option<SweepHit> = None;
+
+        self.for_each_solid_in(lo, hi, |pos, material| {
+            let voxel_min = pos.as_vec3();
+            let voxel_max = voxel_min + 1.0;
+            let mut t_enter = f32::NEG_INFINITY;
+            let mut t_exit = f32::INFINITY;
+            let mut normal = Vec3::ZERO;
+
+            for axis in 0..3 {
+                let (a_min, a_max) = (min[axis], max[axis]);
+                let (b_min, b_max) = (voxel_min[axis], voxel_max[axis]);
+                let m = motion[axis];
+                if m == 0.0 {
+                    if a_max <= b_min || a_min >= b_max {
+                        return;
+                    }
+                    continue;
+                }
+                let (enter, exit) = if m > 0.0 {
+                    ((b_min - a_max) / m, (b_max - a_min) / m)
+                } else {
+                    ((b_max - a_min) / m, (b_min - a_max) / m)
+                };
+                if enter > t_enter {
+                    t_enter = enter;
+                    normal = Vec3::ZERO;
+                    normal[axis] = -m.signum();
+                }
+                t_exit = t_exit.min(exit);
+            }
+
+            if t_enter >= t_exit || t_exit <= 0.0 || t_enter > 1.0 {
+                return;
+            }
+            if t_enter < 0.0 {
+                // Already overlapping: block only motion into the voxel.
+                let (out, _) = min_penetration(min, max, voxel_min, voxel_max);
+                if motion.dot(out) >= 0.0 {
+                    return;
+                }
+                normal = out;
+            }
+
+            let toi = t_enter.max(0.0);
+            if best.is_none_or(|b| toi < b.toi) {
+                best = Some(SweepHit {
+                    toi,
+                    pos,
+                    material,
+                    normal,
+                });
+            }
+        });
+        best
+    }
+
+    /// Voxel ray pick. Empty sectors and bricks are crossed in a single step.
+    ///
+    /// Steps from cell to cell with integer coordinates (DDA), so it does not depend on nudging
+    /// `t` past a boundary, which stops working once `t` is large.
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<RayHit> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return None;
+        }
+        let inv_dir = dir.recip();
+        let mut t = 0.0;
+        let mut cell = origin.floor().as_ivec3();
+        let mut normal = Vec3::ZERO;
+
+        while t <= max_distance {
+            let shift = if !self.sectors.contains_key(&(cell >> 6)) {
+                6
+            } else if self.get_brick_at(cell).is_none() {
+                2
+            } else {
+                let material = self.get_voxel(cell);
+                if material != 0 {
+                    return Some(RayHit {
+                        pos: cell,
+                        material,
+                        normal,
+                        distance: t,
+                    });
+                }
+                0
+            };
+
+            // Leave the empty cube of size 2^shift around `cell` through its nearest face.
+            let cube_min: IVec3 = (cell >> shift) << shift;
+            let cube_max = cube_min + IVec3::splat((1 << shift) - 1);
+            let mut t_exit = f32::INFINITY;
+            let mut exit_axis = 0;
+            for axis in 0..3 {
+                if dir[axis] == 0.0 {
+                    continue;
+                }
+                let plane = if dir[axis] > 0.0 {
+                    cube_max[axis] + 1
+                } else {
+                    cube_min[axis]
+                };
+                let t_axis = (plane as f32 - origin[axis]) * inv_dir[axis];
+                if t_axis < t_exit {
+                    t_exit = t_axis;
+                    exit_axis = axis;
+                }
+            }
+            t = t_exit.max(t);
+
+            // The exit axis steps to the neighbouring cube exactly, the other axes come from the
+            // exit point and are kept on the face the ray actually left through.
+            let exit = (origin + dir * t)
+                .floor()
+                .as_ivec3()
+                .clamp(cube_min, cube_max);
+            cell = exit;
+            cell[exit_axis] = if dir[exit_axis] > 0.0 {
+                cube_max[exit_axis] + 1
+            } else {
+                cube_min[exit_axis] - 1
+            };
+            normal = Vec3::ZERO;
+            normal[exit_axis] = -dir[exit_axis].signum();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world(voxels: impl IntoIterator<Item = IVec3>) -> VoxelWorld {
+        let mut world = VoxelWorld::default();
+        for pos in voxels {
+            world.set_voxel(pos, 3);
+        }
+        world
+    }
+
+    fn floor() -> VoxelWorld {
+        world((0..4).flat_map(|x| (0..4).map(move |z| IVec3::new(x, 0, z))))
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    /// Scattered voxels on both sides of sector and cell borders, including negative ones.
+    fn scattered() -> Vec<IVec3> {
+        (0..400)
+            .map(|i: i32| {
+                let h = i.wrapping_mul(0x2c1b_3c6d) ^ i.wrapping_mul(0x297a_2d39) >> 7;
+                IVec3::new(h % 80, (h >> 8) % 40, (h >> 16) % 80)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn for_each_solid_in_matches_a_voxel_by_voxel_scan() {
+        let world = world(scattered());
+        let ranges = [
+            (IVec3::new(-80, -40, -80), IVec3::new(80, 40, 80)),
+            (IVec3::new(-20, -5, 3), IVec3::new(70, 17, 64)),
+            (IVec3::new(15, 15, 15), IVec3::new(16, 16, 16)),
+            (IVec3::new(-1, 0, -64), IVec3::new(-1, 63, 0)),
+        ];
+        for (min, max) in ranges {
+            let mut expected = Vec::new();
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        if world.is_solid(IVec3::new(x, y, z)) {
+                            expected.push(IVec3::new(x, y, z));
+                        }
+                    }
+                }
+            }
+            let mut found = Vec::new();
+            world.for_each_solid_in(min, max, |pos, _| found.push(pos));
+            found.sort_by_key(|p| [p.z, p.y, p.x]);
+            assert_eq!(found, expected, "{} ..= {}", min, max);
+        }
+    }
+
+    #[test]
+    fn for_each_solid_in_skips_empty_sectors_of_huge_ranges() {
+        // 2^15 sectors per axis, only the two that exist are visited.
+        let world = world([IVec3::new(-900_000, 5, 3), IVec3::new(700_000, -2, 1)]);
+        let mut found = Vec::new();
+        let reach = IVec3::splat(1 << 20);
+        world.for_each_solid_in(-reach, reach, |pos, _| found.push(pos));
+        assert_eq!(
+            found,
+            [IVec3::new(700_000, -2, 1), IVec3::new(-900_000, 5, 3)]
+        );
+    }
+
+    #[test]
+    fn overlap_aabb_pushes_out_along_the_shallowest_axis() {
+        let world = world([IVec3::ZERO]);
+        let contacts = world.overlap_aabb(Vec3::new(0.5, 0.8, 0.5), Vec3::new(1.5, 1.8, 1.5));
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].pos, IVec3::ZERO);
+        assert_eq!(contacts[0].material, 3);
+        assert_eq!(contacts[0].normal, Vec3::Y);
+        assert_close(contacts[0].depth, 0.2);
+    }
+
+    #[test]
+    fn overlap_aabb_ignores_touching_faces() {
+        let world = world([IVec3::ZERO]);
+        assert!(
+            world
+                .overlap_aabb(Vec3::new(0.0, 1.0, 0.0), Vec3::ONE + Vec3::Y)
+                .is_empty()
+        );
+        assert!(
+            world
+                .overlap_aabb(Vec3::new(1.0, 0.0, 0.0), Vec3::ONE + Vec3::X)
+                .is_empty()
+        );
+        assert!(!world.overlaps_aabb(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn overlap_aabb_crosses_brick_and_sector_borders() {
+        let world = world([
+            IVec3::new(3, 0, 0),
+            IVec3::new(4, 0, 0),
+            IVec3::new(64, 0, 0),
+        ]);
+        let contacts = world.overlap_aabb(Vec3::new(2.5, 0.0, 0.0), Vec3::new(64.5, 0.5, 0.5));
+        let mut positions: Vec<IVec3> = contacts.iter().map(|c| c.pos).collect();
+        positions.sort_by_key(|p| p.x);
+        assert_eq!(
+            positions,
+            [
+                IVec3::new(3, 0, 0),
+                IVec3::new(4, 0, 0),
+                IVec3::new(64, 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn overlap_sphere_face_and_corner() {
+        let world = world([IVec3::ZERO]);
+
+        let face = world.overlap_sphere(Vec3::new(0.5, 1.3, 0.5), 0.5);
+        assert_eq!(face.len(), 1);
+        assert_eq!(face[0].normal, Vec3::Y);
+        assert_close(face[0].depth, 0.2);
+
+        let corner = world.overlap_sphere(Vec3::new(1.3, 1.3, 0.5), 0.5);
+        assert_eq!(corner.len(), 1);
+        assert!((corner[0].normal - Vec3::new(1.0, 1.0, 0.0).normalize()).length() < 1e-4);
+        assert_close(corner[0].depth, 0.5 - 0.18f32.sqrt());
+
+        // The bounding boxes overlap but the sphere misses the corner.
+        assert!(
+            world
+                .overlap_sphere(Vec3::new(1.4, 1.4, 0.5), 0.5)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn overlap_sphere_with_center_inside_a_voxel() {
+        let world = world([IVec3::ZERO]);
+        let contacts = world.overlap_sphere(Vec3::new(0.5, 0.9, 0.5), 0.2);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].normal, Vec3::Y);
+        assert_close(contacts[0].depth, 0.3);
+    }
+
+    #[test]
+    fn sweep_aabb_hits_each_axis() {
+        let floor = floor();
+        let (min, max) = (Vec3::new(1.25, 2.0, 1.25), Vec3::new(1.75, 3.0, 1.75));
+        let hit = floor
+            .sweep_aabb(min, max, Vec3::new(0.0, -4.0, 0.0))
+            .unwrap();
+        assert_close(hit.toi, 0.25);
+        assert_eq!(hit.normal, Vec3::Y);
+        assert_eq!(hit.pos.y, 0);
+        assert!(
+            floor
+                .sweep_aabb(min, max, Vec3::new(0.0, -0.5, 0.0))
+                .is_none()
+        );
+
+        let wall = world([IVec3::new(5, 1, 0)]);
+        let (min, max) = (Vec3::new(3.0, 1.2, 0.2), Vec3::new(4.0, 1.8, 0.8));
+        let hit = wall.sweep_aabb(min, max, Vec3::new(3.0, 0.0, 0.0)).unwrap();
+        assert_close(hit.toi, 1.0 / 3.0);
+        assert_eq!(hit.normal, Vec3::NEG_X);
+        assert_eq!(hit.pos, IVec3::new(5, 1, 0));
+
+        let wall = world([IVec3::new(0, 1, -3)]);
+        let (min, max) = (Vec3::new(0.2, 1.2, 0.0), Vec3::new(0.8, 1.8, 1.0));
+        let hit = wall
+            .sweep_aabb(min, max, Vec3::new(0.0, 0.0, -4.0))
+            .unwrap();
+        assert_close(hit.toi, 0.5);
+        assert_eq!(hit.normal, Vec3::Z);
+    }
+
+    #[test]
+    fn sweep_aabb_grazing_does_not_hit() {
+        // Sliding on top of the floor and along the side of a wall, touching but never inside.
+        let floor = floor();
+        let (min, max) = (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 2.0, 1.0));
+        assert!(
+            floor
+                .sweep_aabb(min, max, Vec3::new(3.0, 0.0, 2.0))
+                .is_none()
+        );
+
+        let wall = world([IVec3::new(3, 0, 0)]);
+        let (min, max) = (Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 1.0, 2.0));
+        assert!(
+            wall.sweep_aabb(min, max, Vec3::new(4.0, 0.0, 0.0))
+                .is_none()
+        );
+
+        // Passing exactly over the edge of a voxel.
+        let (min, max) = (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 2.0, 1.0));
+        assert!(
+            wall.sweep_aabb(min, max, Vec3::new(5.0, 0.0, 0.0))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn sweep_aabb_starting_inside_only_blocks_moving_deeper() {
+        let floor = floor();
+        let (min, max) = (Vec3::new(1.25, 0.9, 1.25), Vec3::new(1.75, 1.9, 1.75));
+        let hit = floor
+            .sweep_aabb(min, max, Vec3::new(0.0, -1.0, 0.0))
+            .unwrap();
+        assert_eq!(hit.toi, 0.0);
+        assert_eq!(hit.normal, Vec3::Y);
+        assert!(
+            floor
+                .sweep_aabb(min, max, Vec3::new(0.0, 1.0, 0.0))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn raycast_reports_normal_and_distance_for_each_face() {
+        let target = IVec3::new(10, 10, 10);
+        let world = world([target]);
+        let center = target.as_vec3() + 0.5;
+        for normal in [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ] {
+            // Off center so the ray does not run along cell edges.
+            let origin = center + normal * 5.0 + (Vec3::ONE - normal.abs()) * 0.2;
+            let hit = world.raycast(origin, -normal, 100.0).unwrap();
+            assert_eq!(hit.pos, target, "face {}", normal);
+            assert_eq!(hit.normal, normal, "face {}", normal);
+            assert_close(hit.distance, 4.5);
+            assert!(world.raycast(origin, -normal, 4.4).is_none());
+        }
+    }
+
+    #[test]
+    fn raycast_from_inside_a_voxel() {
+        let world = world([IVec3::ZERO]);
+        let hit = world.raycast(Vec3::splat(0.5), Vec3::X, 10.0).unwrap();
+        assert_eq!(hit.pos, IVec3::ZERO);
+        assert_eq!(hit.normal, Vec3::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn raycast_diagonal_through_cell_corners() {
+        let world = world([IVec3::new(3, 3, 0)]);
+        let hit = world
+            .raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::new(1.0, 1.0, 0.0), 100.0)
+            .unwrap();
+        assert_eq!(hit.pos, IVec3::new(3, 3, 0));
+        assert_close(hit.distance, 2.5 * 2.0f32.sqrt());
+    }
+
+    #[test]
+    fn raycast_far_from_the_origin() {
+        // f32 spacing is far above 1e-4 out here, the ray still has to step cell by cell.
+        for x in [-200_000, 200_000] {
+            let world = world([IVec3::new(x, 0, 0), IVec3::new(x, 5, 0)]);
+            let dir = Vec3::X * x.signum() as f32;
+            let hit = world.raycast(Vec3::splat(0.5), dir, 300_000.0).unwrap();
+            assert_eq!(hit.pos, IVec3::new(x, 0, 0));
+            assert_eq!(hit.normal, -dir);
+            assert!((hit.distance - (x.abs() as f32 - 0.5)).abs() < 0.05);
+
+            let origin = Vec3::new(x as f32 - 10.5 * dir.x, 5.5, 0.5);
+            let hit = world.raycast(origin, dir, 100.0).unwrap();
+            assert_eq!(hit.pos, IVec3::new(x, 5, 0));
+            assert!(world.raycast(origin, -dir, 300_000.0).is_none());
+        }
+    }
+}
@@ -1,6 +1,11 @@
-use crate::config::{AppSettings, DispatchParams, Node};
-use crate::render::VoxelCamera;
-use crate::voxel_map::SvoStorage;
+use crate::benchmark::Benchmark;
+use crate::config::{
+    AppSettings, AtmosphereSettings, DispatchParams, LevelOfDetail, Material, Node,
+    RenderDebugMode, TemporalAntiAliasing,
+};
+use crate::render::{DisplayImage, RenderResolution, VoxelCamera};
+use crate::surface::{MAX_TEXTURE_LAYERS, TEXTURE_SIZE};
+use crate::voxel_map::{SvoStorage, VoxelWorld};
 use bevy::prelude::*;
 use bevy::render::render_resource::{ShaderRef, StorageTextureAccess, TextureFormat};
 use bevy_app_compute::prelude::*;
@@ -13,6 +18,15 @@ impl ComputeShader for VoxelShader {
     }
 }
 
+#[derive(TypePath)]
+pub struct TaaResolveShader;
+
+impl ComputeShader for TaaResolveShader {
+    fn shader() -> ShaderRef {
+        "shaders/taa.wgsl".into()
+    }
+}
+
 #[derive(Resource)]
 pub struct WriteTextureWorker;
 
@@ -20,13 +34,33 @@ impl ComputeWorker for WriteTextureWorker {
     fn build(world: &mut World) -> AppComputeWorker<Self> {
         let (width, height, workgroup_size) = {
             let settings = world.resource::<AppSettings>();
-            (settings.width, settings.height, settings.workgroup_size)
+            (
+                settings.render_width(),
+                settings.render_height(),
+                settings.workgroup_size,
+            )
         };
 
+        let workgroups = [
+            (width + workgroup_size - 1) / workgroup_size,
+            (height + workgroup_size - 1) / workgroup_size,
+            1,
+        ];
+        let pixel_count = (width * height) as usize;
+
         AppComputeWorkerBuilder::new(world)
             .add_uniform("pc", &DispatchParams::default())
             .add_storage("nodePool", &vec![Node::default(); 600_000])
             .add_storage("leafData", &vec![0u32; 600_000])
+            .add_storage("palette", &vec![Material::default(); 256])
+            .add_storage(
+                "texels",
+                &vec![u32::MAX; (MAX_TEXTURE_LAYERS * TEXTURE_SIZE * TEXTURE_SIZE) as usize],
+            )
+            .add_rw_storage("frame", &vec![Vec4::ZERO; pixel_count])
+            .add_rw_storage("history_a", &vec![Vec4::ZERO; pixel_count])
+            .add_rw_storage("history_b", &vec![Vec4::ZERO; pixel_count])
+            .add_staging("stats", &[0u32; 2])
             .add_texture(
                 "out_tex",
                 width,
@@ -34,23 +68,63 @@ impl ComputeWorker for WriteTextureWorker {
                 TextureFormat::Rgba8Unorm,
                 StorageTextureAccess::WriteOnly,
             )
+            .add_texture(
+                "depth_tex",
+                width,
+                height,
+                TextureFormat::R32Float,
+                StorageTextureAccess::WriteOnly,
+            )
             .add_pass::<VoxelShader>(
-                [
-                    (width + workgroup_size - 1) / workgroup_size,
-                    (height + workgroup_size - 1) / workgroup_size,
-                    1,
+                workgroups,
+                &[
+                    "pc",
+                    "nodePool",
+                    "leafData",
+                    "palette",
+                    "frame",
+                    "depth_tex",
+                    "stats",
+                    "texels",
                 ],
-                &["pc", "nodePool", "leafData", "out_tex"],
+            )
+            .add_pass::<TaaResolveShader>(
+                workgroups,
+                &["pc", "frame", "history_a", "history_b", "out_tex"],
             )
             .continuous()
             .build()
     }
 }
 
+#[derive(Default)]
+pub struct TemporalState {
+    frame: u32,
+    prev_view_proj: Option<Mat4>,
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut f = 1.0;
+    let mut r = 0.0;
+    while index > 0 {
+        f /= base as f32;
+        r += f * (index % base) as f32;
+        index /= base;
+    }
+    r
+}
+
 pub fn handle_compute_params(
     mut worker: ResMut<AppComputeWorker<WriteTextureWorker>>,
     camera_q: Query<(&Camera, &GlobalTransform), With<VoxelCamera>>,
     svo: Res<SvoStorage>,
+    debug_mode: Res<RenderDebugMode>,
+    taa: Res<TemporalAntiAliasing>,
+    atmosphere: Res<AtmosphereSettings>,
+    lod: Res<LevelOfDetail>,
+    resolution: Res<RenderResolution>,
+    benchmark: Res<Benchmark>,
+    mut temporal: Local<TemporalState>,
 ) {
     let Ok((camera, transform)) = camera_q.single() else {
         return;
@@ -59,17 +133,83 @@ pub fn handle_compute_params(
     let projection = camera.clip_from_view();
     let camera_world_matrix = transform.compute_matrix();
     let view = camera_world_matrix.inverse();
-    let inv_view_proj = (projection * view).inverse();
+    let view_proj = projection * view;
+
+    // Buffers are recreated on resize, so the history starts over.
+    if resolution.is_changed() || !taa.enabled {
+        temporal.prev_view_proj = None;
+    }
+    let history_valid = temporal.prev_view_proj.is_some();
+    let prev_view_proj = temporal.prev_view_proj.unwrap_or(view_proj);
+
+    let jitter = if taa.enabled {
+        let sample = temporal.frame % 8 + 1;
+        Vec2::new(halton(sample, 2), halton(sample, 3)) - 0.5
+    } else {
+        Vec2::ZERO
+    };
+
+    // Angle of one pixel at the center of the screen, from the projection's vertical focal length.
+    let pixel_angle = if lod.enabled {
+        2.0 / (projection.y_axis.y * resolution.0.y.max(1) as f32) * lod.pixel_scale
+    } else {
+        0.0
+    };
 
     let params = DispatchParams {
-        inv_view_proj,
+        inv_view_proj: view_proj.inverse(),
+        view_proj,
         camera_origin: Vec4::new(
             transform.translation().x,
             transform.translation().y,
             transform.translation().z,
             svo.tree_scale as f32,
         ),
+        debug: UVec4::new(*debug_mode as u32, benchmark.running() as u32, 0, 0),
+        prev_view_proj,
+        frame: UVec4::new(
+            resolution.0.x,
+            resolution.0.y,
+            temporal.frame,
+            history_valid as u32,
+        ),
+        jitter: Vec4::new(jitter.x, jitter.y, taa.blend, 0.0),
+        sun_direction: atmosphere
+            .sun_direction
+            .normalize_or(Vec3::Y)
+            .extend(atmosphere.sun_angular_radius.cos()),
+        sun_color: atmosphere.sun_color.extend(atmosphere.sun_intensity),
+        zenith_color: atmosphere.zenith_color.extend(1.0),
+        horizon_color: atmosphere.horizon_color.extend(1.0),
+        fog: Vec4::new(
+            atmosphere.fog_density,
+            atmosphere.fog_start,
+            atmosphere.fog_sun_scattering,
+            0.0,
+        ),
+        lod: Vec4::new(pixel_angle, 0.0, 0.0, 0.0),
     };
 
     worker.write("pc", &params);
+
+    temporal.frame = temporal.frame.wrapping_add(1);
+    if taa.enabled {
+        temporal.prev_view_proj = Some(view_proj);
+    }
+}
+
+pub fn upload_to_gpu(
+    svo: Res<SvoStorage>,
+    world: Res<VoxelWorld>,
+    mut worker: ResMut<AppComputeWorker<WriteTextureWorker>>,
+    display_image: Res<DisplayImage>,
+) {
+    if svo.is_changed() || display_image.is_changed() {
+        worker.write_slice("nodePool", &svo.nodes);
+        worker.write_slice("leafData", &svo.leaf_data);
+        println!("Uploaded NodePool");
+    }
+    if world.is_changed() || display_image.is_changed() {
+        worker.write_slice("palette", &world.palette);
+    }
 }
@@ -1,12 +1,38 @@
-use bevy::prelude::{Mat4, Reflect, Resource, UVec4, Vec4};
+use bevy::prelude::{Mat4, Reflect, Resource, UVec4, Vec3, Vec4};
 use bevy::render::render_resource::ShaderType;
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
 
-#[derive(Resource)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpscaleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Bilinear upscale followed by contrast adaptive sharpening.
+    Sharpened,
+}
+
+impl UpscaleFilter {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Nearest => Self::Bilinear,
+            Self::Bilinear => Self::Sharpened,
+            Self::Sharpened => Self::Nearest,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppSettings {
     pub width: u32,
     pub height: u32,
     pub workgroup_size: u32,
+    /// Resolution of the compute pass relative to the window, above 1.0 supersamples. Captures
+    /// are box filtered back down to the window size.
+    pub render_scale: f32,
+    pub upscale_filter: UpscaleFilter,
+    pub sharpness: f32,
 }
 
 impl Default for AppSettings {
@@ -15,17 +41,61 @@ impl Default for AppSettings {
             width: 700,
             height: 512,
             workgroup_size: 8,
+            render_scale: 1.0,
+            upscale_filter: UpscaleFilter::default(),
+            sharpness: 0.5,
         }
     }
 }
 
+impl AppSettings {
+    pub const MIN_RENDER_SCALE: f32 = 0.25;
+    pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+    pub fn render_width(&self) -> u32 {
+        ((self.width as f32 * self.render_scale).ceil() as u32).max(1)
+    }
+
+    pub fn render_height(&self) -> u32 {
+        ((self.height as f32 * self.render_scale).ceil() as u32).max(1)
+    }
+}
+
+/// Values of `Material.phase`.
+pub const PHASE_SOLID: u32 = 0;
+/// Falls and piles up at an angle of repose given by `friction`.
+pub const PHASE_GRANULAR: u32 = 1;
+pub const PHASE_LIQUID: u32 = 2;
+
+/// Values of `Material.pattern`.
+pub const PATTERN_NONE: u32 = 0;
+/// Value noise in voxel space, breaks up large flat areas.
+pub const PATTERN_NOISE: u32 = 1;
+/// Running bond bricks with darkened mortar lines.
+pub const PATTERN_BRICKS: u32 = 2;
+
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Reflect, ShaderType, Pod, Zeroable)]
+#[derive(Clone, Copy, Debug, PartialEq, Reflect, ShaderType, Pod, Zeroable, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Material {
     pub color : [f32;3],
     pub yield_strength : f32,
     pub density : f32,
     pub friction : f32,
+    pub phase : u32,
+    /// Temperatures in degrees Celsius, infinite if the material never burns or melts.
+    pub ignition_temperature : f32,
+    pub melting_temperature : f32,
+    /// Fraction of a temperature difference exchanged with a neighbour per second.
+    pub conductivity : f32,
+    /// Layer of the surface texture atlas, tinted by `color`. -1 for none.
+    pub texture_layer : i32,
+    /// One of the `PATTERN_*` values, applied on top of the texture.
+    pub pattern : u32,
+    /// Size in voxels of one pattern cell.
+    pub pattern_scale : f32,
+    /// How much the pattern darkens the albedo, from 0 to 1.
+    pub pattern_strength : f32,
 }
 
 impl Default for Material {
@@ -34,7 +104,15 @@ impl Default for Material {
             color : [0.5, 0.5, 0.5],
             yield_strength : 100.0,
             density : 2500.0,
-            friction : 0.5
+            friction : 0.5,
+            phase : PHASE_SOLID,
+            ignition_temperature : f32::INFINITY,
+            melting_temperature : f32::INFINITY,
+            conductivity : 0.5,
+            texture_layer : -1,
+            pattern : PATTERN_NONE,
+            pattern_scale : 4.0,
+            pattern_strength : 0.3,
         }
     }
 }
@@ -62,25 +140,178 @@ impl Node {
                 ]
         }
     }
+
+    /// Averaged color of the voxels below the node and how many there are, drawn instead of
+    /// the children once they are smaller than a pixel.
+    pub fn lod(&self) -> (Vec3, f32) {
+        let [r, g, b, a] = self.packed_data[3].to_le_bytes();
+        let color = Vec3::new(r as f32, g as f32, b as f32) / 255.0;
+        (color, (a as f32 / 4.0).exp2())
+    }
+
+    /// The voxel count is kept as log2 in quarter steps, it only weighs the parent's average.
+    pub fn set_lod(&mut self, color: Vec3, voxel_count: f32) {
+        let c = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round();
+        let a = (voxel_count.max(1.0).log2() * 4.0).round().min(255.0);
+        self.packed_data[3] = u32::from_le_bytes([c.x as u8, c.y as u8, c.z as u8, a as u8]);
+    }
+
+    /// Sets the LOD color of a parent from its `children`, weighted by their voxel counts.
+    pub fn average_lod(&mut self, children: &[Node]) {
+        let (mut color, mut count) = (Vec3::ZERO, 0.0);
+        for child in children {
+            let (child_color, child_count) = child.lod();
+            color += child_color * child_count;
+            count += child_count;
+        }
+        if count > 0.0 {
+            self.set_lod(color / count, count);
+        }
+    }
 }
 
 #[repr(C)]
 #[derive(ShaderType, Clone, Copy, Debug, Pod, Zeroable)]
 pub struct DispatchParams {
     pub inv_view_proj: Mat4,
+    pub view_proj: Mat4,
     pub camera_origin: Vec4,
+    /// x: `RenderDebugMode`, y: 1 while benchmarking, to count traversal steps.
+    pub debug: UVec4,
+    pub prev_view_proj: Mat4,
+    /// xy: render size, z: frame index, w: 1 when the history buffer is valid.
+    pub frame: UVec4,
+    /// xy: subpixel jitter in pixels, z: history blend factor.
+    pub jitter: Vec4,
+    /// xyz: direction towards the sun, w: cosine of the sun disk radius.
+    pub sun_direction: Vec4,
+    /// rgb: sun color, w: intensity.
+    pub sun_color: Vec4,
+    pub zenith_color: Vec4,
+    pub horizon_color: Vec4,
+    /// x: density, y: start distance, z: sun scattering strength.
+    pub fog: Vec4,
+    /// x: radians covered by one pixel times `LevelOfDetail.pixel_scale`, 0 when disabled.
+    pub lod: Vec4,
 }
 
 impl Default for DispatchParams {
     fn default() -> Self {
         Self {
             inv_view_proj: Mat4::IDENTITY,
+            view_proj: Mat4::IDENTITY,
             camera_origin: Vec4::ZERO,
+            debug: UVec4::ZERO,
+            prev_view_proj: Mat4::IDENTITY,
+            frame: UVec4::ZERO,
+            jitter: Vec4::ZERO,
+            sun_direction: Vec4::Y,
+            sun_color: Vec4::ONE,
+            zenith_color: Vec4::ZERO,
+            horizon_color: Vec4::ZERO,
+            fog: Vec4::ZERO,
+            lod: Vec4::ZERO,
         }
     }
 }
 
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct AtmosphereSettings {
+    /// Direction towards the sun, also drives the scene's `DirectionalLight`.
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    pub sun_intensity: f32,
+    /// Angular radius of the sun disk in radians.
+    pub sun_angular_radius: f32,
+    pub zenith_color: Vec3,
+    pub horizon_color: Vec3,
+    pub fog_density: f32,
+    pub fog_start: f32,
+    pub fog_sun_scattering: f32,
+}
+
+impl Default for AtmosphereSettings {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vec3::new(0.37, 0.86, 0.35).normalize(),
+            sun_color: Vec3::new(1.0, 0.95, 0.85),
+            sun_intensity: 1.0,
+            sun_angular_radius: 0.02,
+            zenith_color: Vec3::new(0.22, 0.45, 0.85),
+            horizon_color: Vec3::new(0.53, 0.81, 0.98),
+            fog_density: 0.004,
+            fog_start: 64.0,
+            fog_sun_scattering: 0.5,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TemporalAntiAliasing {
+    pub enabled: bool,
+    /// Weight of the current frame when blending into the history.
+    pub blend: f32,
+}
+
+impl Default for TemporalAntiAliasing {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            blend: 0.1,
+        }
+    }
+}
+
+/// Stops rays at interior nodes that project to less than `pixel_scale` pixels and shades
+/// them with the node's averaged color.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct LevelOfDetail {
+    pub enabled: bool,
+    pub pixel_scale: f32,
+}
+
+impl Default for LevelOfDetail {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            pixel_scale: 1.0,
+        }
+    }
+}
+
+/// What the ray marcher writes to the screen, cycled with a hotkey.
+#[derive(Resource, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum RenderDebugMode {
+    /// Palette colors lit by the sun. Replaced the old normals-only output as the default once
+    /// the palette was uploaded, so materials can be told apart.
+    #[default]
+    Shaded,
+    /// The previous default output, world space normals mapped to 0..1.
+    Normals,
+    MaterialId,
+    Albedo,
+    Steps,
+    Depth,
+    NodeLevel,
+}
+
+impl RenderDebugMode {
+    const ALL: [Self; 7] = [
+        Self::Shaded,
+        Self::Normals,
+        Self::MaterialId,
+        Self::Albedo,
+        Self::Steps,
+        Self::Depth,
+        Self::NodeLevel,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Brick {
     pub voxels : [u8; 64]
 }
@@ -0,0 +1,517 @@
+use crate::history::EditHistory;
+use crate::render::VoxelCamera;
+use crate::structure::NEIGHBOURS;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited};
+use bevy::input::mouse::MouseWheel;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorTool {
+    Place,
+    Remove,
+    /// Changes the material of solid voxels without adding or removing any.
+    Paint,
+    /// Majority filter over the brush footprint, rounds off edges and fills small dents.
+    Smooth,
+    /// Replaces the connected region of the clicked material.
+    FloodFill,
+    /// Picks the corners of the copy region, see `prefab::clipboard_input`.
+    Select,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Voxel,
+    Sphere,
+    Box,
+    /// Upright cylinder, as tall as it is wide.
+    Cylinder,
+}
+
+impl BrushShape {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Voxel => Self::Sphere,
+            Self::Sphere => Self::Box,
+            Self::Box => Self::Cylinder,
+            Self::Cylinder => Self::Voxel,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct EditorState {
+    pub enabled: bool,
+    pub tool: EditorTool,
+    pub brush: BrushShape,
+    /// Brush radius in voxels.
+    pub size: i32,
+    pub max_size: i32,
+    pub material: u8,
+    pub reach: f32,
+    /// Flood fills stop after this many voxels.
+    pub max_fill: usize,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tool: EditorTool::Place,
+            brush: BrushShape::Voxel,
+            size: 3,
+            max_size: 32,
+            material: 1,
+            reach: 1024.0,
+            max_fill: 65_536,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PalettePicker;
+
+#[derive(Component)]
+pub struct PaletteButton(pub u8);
+
+#[derive(Component)]
+pub struct EditorStatus;
+
+/// Voxels covered by the brush centered on `center`.
+fn brush_footprint(brush: BrushShape, size: i32, center: IVec3) -> Vec<IVec3> {
+    if brush == BrushShape::Voxel {
+        return vec![center];
+    }
+    let mut voxels = Vec::new();
+    for y in -size..=size {
+        for z in -size..=size {
+            for x in -size..=size {
+                let inside = match brush {
+                    BrushShape::Sphere => x * x + y * y + z * z <= size * size,
+                    BrushShape::Cylinder => x * x + z * z <= size * size,
+                    _ => true,
+                };
+                if inside {
+                    voxels.push(center + IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+    voxels
+}
+
+/// New material for each voxel of the footprint whose 26-neighbourhood disagrees with it.
+fn smooth(world: &VoxelWorld, footprint: &[IVec3]) -> Vec<(IVec3, u8)> {
+    let mut changes = Vec::new();
+    for &pos in footprint {
+        let mut counts: HashMap<u8, u32> = HashMap::default();
+        let mut solid = 0;
+        for y in -1..=1 {
+            for z in -1..=1 {
+                for x in -1..=1 {
+                    let offset = IVec3::new(x, y, z);
+                    let mat_id = world.get_voxel(pos + offset);
+                    if offset != IVec3::ZERO && mat_id != 0 {
+                        solid += 1;
+                        *counts.entry(mat_id).or_default() += 1;
+                    }
+                }
+            }
+        }
+        let mat_id = world.get_voxel(pos);
+        if mat_id != 0 && solid < 9 {
+            changes.push((pos, 0));
+        } else if mat_id == 0 && solid > 17 {
+            let fill = counts
+                .iter()
+                .max_by_key(|&(id, n)| (*n, *id))
+                .map_or(0, |(id, _)| *id);
+            changes.push((pos, fill));
+        }
+    }
+    changes
+}
+
+/// Connected voxels of the material at `seed`, at most `limit` of them.
+fn flood_region(world: &VoxelWorld, seed: IVec3, limit: usize) -> Vec<IVec3> {
+    let mat_id = world.get_voxel(seed);
+    if mat_id == 0 {
+        return Vec::new();
+    }
+    let mut visited: HashSet<IVec3> = HashSet::from_iter([seed]);
+    let mut region = vec![seed];
+    let mut queue = VecDeque::from([seed]);
+    while let Some(pos) = queue.pop_front() {
+        for offset in NEIGHBOURS {
+            let next = pos + offset;
+            if region.len() >= limit {
+                return region;
+            }
+            if world.get_voxel(next) == mat_id && visited.insert(next) {
+                region.push(next);
+                queue.push_back(next);
+            }
+        }
+    }
+    region
+}
+
+/// Where the brush lands for the ray hit, placing builds out from the face that was hit.
+fn brush_center(state: &EditorState, tool: EditorTool, hit_pos: IVec3, normal: Vec3) -> IVec3 {
+    match tool {
+        EditorTool::Place => {
+            let offset = if state.brush == BrushShape::Voxel {
+                1
+            } else {
+                state.size
+            };
+            hit_pos + normal.as_ivec3() * offset
+        }
+        _ => hit_pos,
+    }
+}
+
+pub fn toggle_editor(keyboard: Res<ButtonInput<KeyCode>>, mut state: ResMut<EditorState>) {
+    if keyboard.just_pressed(KeyCode::Tab) {
+        state.enabled = !state.enabled;
+        println!(
+            "Editor {}",
+            if state.enabled { "enabled" } else { "disabled" }
+        );
+    }
+}
+
+pub fn editor_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    world: Res<VoxelWorld>,
+    mut state: ResMut<EditorState>,
+) {
+    let scroll: f32 = wheel.read().map(|e| e.y.signum()).sum();
+    if !state.enabled {
+        return;
+    }
+    for (key, tool) in [
+        (KeyCode::Digit1, EditorTool::Place),
+        (KeyCode::Digit2, EditorTool::Remove),
+        (KeyCode::Digit3, EditorTool::Paint),
+        (KeyCode::Digit4, EditorTool::Smooth),
+        (KeyCode::Digit5, EditorTool::FloodFill),
+        (KeyCode::Digit6, EditorTool::Select),
+    ] {
+        if keyboard.just_pressed(key) {
+            state.tool = tool;
+        }
+    }
+    if keyboard.just_pressed(KeyCode::KeyB) {
+        state.brush = state.brush.next();
+    }
+
+    let mut grow = scroll as i32;
+    if keyboard.just_pressed(KeyCode::Equal) {
+        grow += 1;
+    }
+    if keyboard.just_pressed(KeyCode::Minus) {
+        grow -= 1;
+    }
+    state.size = (state.size + grow).clamp(1, state.max_size);
+
+    let count = world.palette.len().max(2) as i32;
+    let mut material = state.material as i32;
+    if keyboard.just_pressed(KeyCode::KeyE) {
+        material += 1;
+    }
+    if keyboard.just_pressed(KeyCode::KeyQ) {
+        material -= 1;
+    }
+    // Material 0 is air and never picked.
+    state.material = ((material - 1).rem_euclid(count - 1) + 1) as u8;
+}
+
+/// Left click applies the current tool, right click always removes with the brush.
+/// Every click is a single undo step.
+pub fn apply_editor_tool(
+    mouse: Res<ButtonInput<MouseButton>>,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    buttons: Query<&Interaction, With<PaletteButton>>,
+    state: Res<EditorState>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    mut edits: EventWriter<VoxelsEdited>,
+) {
+    if !state.enabled {
+        return;
+    }
+    let tool = if mouse.just_pressed(MouseButton::Left) && state.tool != EditorTool::Select {
+        state.tool
+    } else if mouse.just_pressed(MouseButton::Right) {
+        EditorTool::Remove
+    } else {
+        return;
+    };
+    // Clicks on the palette picker are not edits.
+    if buttons.iter().any(|i| *i != Interaction::None) {
+        return;
+    }
+    let Ok(transform) = camera_q.single() else {
+        return;
+    };
+    let Some(hit) = world.raycast(transform.translation, *transform.forward(), state.reach) else {
+        return;
+    };
+
+    let center = brush_center(&state, tool, hit.pos, hit.normal);
+    let footprint = brush_footprint(state.brush, state.size, center);
+    let changes: Vec<(IVec3, u8)> = match tool {
+        EditorTool::Place => footprint
+            .into_iter()
+            .filter(|&p| !world.is_solid(p))
+            .map(|p| (p, state.material))
+            .collect(),
+        EditorTool::Remove => footprint.into_iter().map(|p| (p, 0)).collect(),
+        EditorTool::Paint => footprint
+            .into_iter()
+            .filter(|&p| world.is_solid(p))
+            .map(|p| (p, state.material))
+            .collect(),
+        EditorTool::Smooth => smooth(&world, &footprint),
+        EditorTool::FloodFill => flood_region(&world, hit.pos, state.max_fill)
+            .into_iter()
+            .map(|p| (p, state.material))
+            .collect(),
+        EditorTool::Select => return,
+    };
+
+    history.begin(&world, format!("{:?} {:?}", tool, state.brush));
+    let mut min = IVec3::MAX;
+    let mut max = IVec3::MIN;
+    for (pos, mat_id) in changes {
+        if history.set_voxel(&mut world, pos, mat_id) != mat_id {
+            min = min.min(pos);
+            max = max.max(pos);
+        }
+    }
+    history.commit(&world);
+    if min.cmple(max).all() {
+        edits.write(VoxelsEdited { min, max });
+    }
+}
+
+/// Draws the brush footprint where the next click would land.
+pub fn draw_brush_preview(
+    mut gizmos: Gizmos,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    state: Res<EditorState>,
+    world: Res<VoxelWorld>,
+) {
+    if !state.enabled || state.tool == EditorTool::Select {
+        return;
+    }
+    let Ok(transform) = camera_q.single() else {
+        return;
+    };
+    let Some(hit) = world.raycast(transform.translation, *transform.forward(), state.reach) else {
+        return;
+    };
+    let color = match state.tool {
+        EditorTool::Remove => Color::srgb(1.0, 0.2, 0.2),
+        _ => {
+            let [r, g, b] = world.material(state.material).color;
+            Color::linear_rgb(r, g, b)
+        }
+    };
+    let center = brush_center(&state, state.tool, hit.pos, hit.normal).as_vec3() + 0.5;
+    if state.tool == EditorTool::FloodFill {
+        gizmos.cuboid(Transform::from_translation(hit.pos.as_vec3() + 0.5), color);
+        return;
+    }
+    let size = state.size as f32;
+    match state.brush {
+        BrushShape::Voxel => gizmos.cuboid(Transform::from_translation(center), color),
+        BrushShape::Sphere => {
+            gizmos.sphere(Isometry3d::from_translation(center), size + 0.5, color);
+        }
+        BrushShape::Box => gizmos.cuboid(
+            Transform::from_translation(center).with_scale(Vec3::splat(size * 2.0 + 1.0)),
+            color,
+        ),
+        BrushShape::Cylinder => {
+            gizmos.primitive_3d(
+                &Cylinder::new(size + 0.5, size * 2.0 + 1.0),
+                Isometry3d::from_translation(center),
+                color,
+            );
+        }
+    }
+}
+
+pub fn setup_editor_ui(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                bottom: Val::Px(8.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            Visibility::Hidden,
+            PalettePicker,
+        ))
+        .with_children(|root| {
+            root.spawn((Text::new(""), TextFont::from_font_size(14.0), EditorStatus));
+        });
+}
+
+/// Keeps the palette buttons in sync with `VoxelWorld.palette` and the selection.
+pub fn update_palette_picker(
+    mut commands: Commands,
+    world: Res<VoxelWorld>,
+    mut state: ResMut<EditorState>,
+    mut picker_q: Query<(Entity, &mut Visibility), With<PalettePicker>>,
+    mut buttons: Query<(&PaletteButton, &Interaction, &mut BorderColor)>,
+    mut status_q: Query<&mut Text, With<EditorStatus>>,
+    mut row: Local<Option<(Entity, usize)>>,
+) {
+    let Ok((picker, mut visibility)) = picker_q.single_mut() else {
+        return;
+    };
+    *visibility = if state.enabled {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+    if !state.enabled {
+        return;
+    }
+
+    if row.is_none_or(|(_, len)| len != world.palette.len()) {
+        if let Some((entity, _)) = *row {
+            commands.entity(entity).despawn();
+        }
+        let entity = commands
+            .spawn(Node {
+                column_gap: Val::Px(4.0),
+                ..default()
+            })
+            .with_children(|row| {
+                for (id, material) in world.palette.iter().enumerate().skip(1) {
+                    let [r, g, b] = material.color;
+                    row.spawn((
+                        Button,
+                        Node {
+                            width: Val::Px(24.0),
+                            height: Val::Px(24.0),
+                            border: UiRect::all(Val::Px(2.0)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::linear_rgb(r, g, b)),
+                        BorderColor(Color::BLACK),
+                        PaletteButton(id as u8),
+                    ));
+                }
+            })
+            .id();
+        commands.entity(picker).add_child(entity);
+        *row = Some((entity, world.palette.len()));
+    }
+
+    for (button, interaction, _) in &buttons {
+        if *interaction == Interaction::Pressed {
+            state.material = button.0;
+        }
+    }
+    for (button, _, mut border) in &mut buttons {
+        border.0 = if button.0 == state.material {
+            Color::WHITE
+        } else {
+            Color::BLACK
+        };
+    }
+    if let Ok(mut text) = status_q.single_mut() {
+        text.0 = format!(
+            "{:?} | {:?} r={} | material {}",
+            state.tool, state.brush, state.size, state.material
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 26 neighbours of the origin.
+    fn neighbourhood() -> Vec<IVec3> {
+        brush_footprint(BrushShape::Box, 1, IVec3::ZERO)
+            .into_iter()
+            .filter(|&p| p != IVec3::ZERO)
+            .collect()
+    }
+
+    fn world(voxels: impl IntoIterator<Item = (IVec3, u8)>) -> VoxelWorld {
+        let mut world = VoxelWorld::default();
+        for (pos, mat_id) in voxels {
+            world.set_voxel(pos, mat_id);
+        }
+        world
+    }
+
+    #[test]
+    fn brush_footprints_have_their_shape() {
+        let center = IVec3::new(10, 20, 30);
+        assert_eq!(brush_footprint(BrushShape::Voxel, 3, center), [center]);
+        for brush in [BrushShape::Sphere, BrushShape::Box, BrushShape::Cylinder] {
+            assert_eq!(brush_footprint(brush, 0, center), [center]);
+        }
+        let count = |brush| brush_footprint(brush, 2, center).len();
+        assert_eq!(count(BrushShape::Box), 125);
+        assert_eq!(count(BrushShape::Sphere), 33);
+        assert_eq!(count(BrushShape::Cylinder), 13 * 5);
+
+        let corner = center + IVec3::new(2, 2, 0);
+        assert!(!brush_footprint(BrushShape::Sphere, 2, center).contains(&corner));
+        assert!(brush_footprint(BrushShape::Cylinder, 2, center).contains(&corner));
+        assert!(brush_footprint(BrushShape::Box, 2, center).contains(&(center + 2)));
+    }
+
+    #[test]
+    fn smooth_removes_thin_voxels_and_fills_pits() {
+        let neighbours = neighbourhood();
+        for (count, kept) in [(8, false), (9, true)] {
+            let voxels = neighbours[..count].iter().map(|&p| (p, 1));
+            let world = world(voxels.chain([(IVec3::ZERO, 1)]));
+            let changes = smooth(&world, &[IVec3::ZERO]);
+            assert_eq!(changes.is_empty(), kept, "{count} neighbours");
+            if !kept {
+                assert_eq!(changes, [(IVec3::ZERO, 0)]);
+            }
+        }
+        for (count, filled) in [(17, false), (18, true)] {
+            // Material 2 is the majority, 1 only fills the last six slots.
+            let voxels = neighbours[..count]
+                .iter()
+                .enumerate()
+                .map(|(i, &p)| (p, if i < count - 6 { 2 } else { 1 }));
+            let changes = smooth(&world(voxels), &[IVec3::ZERO]);
+            if filled {
+                assert_eq!(changes, [(IVec3::ZERO, 2)]);
+            } else {
+                assert!(changes.is_empty(), "{count} neighbours");
+            }
+        }
+    }
+
+    #[test]
+    fn flood_region_follows_one_material_up_to_the_limit() {
+        let line = (0..10).map(|x| (IVec3::X * x, 1));
+        let world = world(line.chain([(IVec3::X * 10, 2), (IVec3::X * 11, 1)]));
+        let region = flood_region(&world, IVec3::ZERO, 100);
+        assert_eq!(region, (0..10).map(|x| IVec3::X * x).collect::<Vec<_>>());
+        assert_eq!(flood_region(&world, IVec3::X * 5, 4).len(), 4);
+        assert_eq!(flood_region(&world, IVec3::X * 11, 100), [IVec3::X * 11]);
+        assert!(flood_region(&world, IVec3::Y, 100).is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::history::EditHistory;
+use crate::render::VoxelCamera;
+use crate::rigid_body::{VoxelBody, body_bundle};
+use crate::structure::StructuralSettings;
+use crate::volume::VoxelVolume;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebrisMode {
+    None,
+    /// Short lived cubes flying away from the blast.
+    Particles,
+    /// Removed voxels are regrouped into small `VoxelBody` chunks.
+    Chunks,
+}
+
+impl DebrisMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Particles,
+            Self::Particles => Self::Chunks,
+            Self::Chunks => Self::None,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ExplosionSettings {
+    pub radius: f32,
+    /// Pressure at the center in kPa, compared against `Material.yield_strength`.
+    pub power: f32,
+    pub debris: DebrisMode,
+    /// Upper bound on particles or chunks spawned per explosion.
+    pub max_debris: usize,
+    /// Edge length of a debris chunk in voxels.
+    pub chunk_size: i32,
+    /// Launch speed in voxels per second, scaled down towards the blast edge.
+    pub debris_speed: f32,
+    pub particle_lifetime: f32,
+}
+
+impl Default for ExplosionSettings {
+    fn default() -> Self {
+        Self {
+            radius: 12.0,
+            power: 400.0,
+            debris: DebrisMode::Particles,
+            max_debris: 256,
+            chunk_size: 2,
+            debris_speed: 60.0,
+            particle_lifetime: 2.0,
+        }
+    }
+}
+
+/// Voxels removed by one blast, sorted by position.
+#[derive(Clone, Debug)]
+pub struct Explosion {
+    pub center: Vec3,
+    pub radius: f32,
+    pub power: f32,
+    pub removed: Vec<(IVec3, u8)>,
+    /// Inclusive bounds of `removed`, `None` when nothing was removed.
+    pub bounds: Option<(IVec3, IVec3)>,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct VoxelsExploded(pub Explosion);
+
+#[derive(Component)]
+pub struct DebrisParticle {
+    pub velocity: Vec3,
+    pub lifetime: f32,
+}
+
+impl VoxelWorld {
+    /// Removes every voxel whose material yields under the blast. Pressure falls off
+    /// linearly from `power` at the center to zero at `radius`.
+    pub fn explode(&mut self, center: Vec3, radius: f32, power: f32) -> Explosion {
+        let lo = (center - radius).floor().as_ivec3();
+        let hi = (center + radius).ceil().as_ivec3();
+        let mut removed = Vec::new();
+        self.for_each_solid_in(lo, hi, |pos, mat_id| {
+            let distance = (pos.as_vec3() + 0.5).distance(center);
+            if distance > radius {
+                return;
+            }
+            let pressure = power * (1.0 - distance / radius);
+            if pressure >= self.material(mat_id).yield_strength {
+                removed.push((pos, mat_id));
+            }
+        });
+
+        removed.sort_by_key(|&(p, _)| (p.y, p.z, p.x));
+        let mut bounds: Option<(IVec3, IVec3)> = None;
+        for &(pos, _) in &removed {
+            self.set_voxel(pos, 0);
+            bounds = Some(bounds.map_or((pos, pos), |(min, max)| (min.min(pos), max.max(pos))));
+        }
+        Explosion {
+            center,
+            radius,
+            power,
+            removed,
+            bounds,
+        }
+    }
+}
+
+/// Cheap per-voxel variation so debris does not leave as a perfect shell.
+fn jitter(pos: IVec3) -> f32 {
+    let h = (pos.x as u32).wrapping_mul(73856093)
+        ^ (pos.y as u32).wrapping_mul(19349663)
+        ^ (pos.z as u32).wrapping_mul(83492791);
+    (h % 1024) as f32 / 1023.0
+}
+
+fn launch_velocity(explosion: &Explosion, point: Vec3, speed: f32) -> Vec3 {
+    let offset = point - explosion.center;
+    let falloff = 1.0 - (offset.length() / explosion.radius).min(1.0);
+    let dir = (offset.normalize_or_zero() + Vec3::Y * 0.5).normalize_or_zero();
+    dir * speed * (0.3 + 0.7 * falloff)
+}
+
+pub fn cycle_debris_mode(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<ExplosionSettings>,
+) {
+    if keyboard.just_pressed(KeyCode::F3) {
+        settings.debris = settings.debris.next();
+        println!("Explosion debris: {:?}", settings.debris);
+    }
+}
+
+pub fn explode_at_cursor(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    mut exploded: EventWriter<VoxelsExploded>,
+    mut edits: EventWriter<VoxelsEdited>,
+    settings: Res<ExplosionSettings>,
+) {
+    // Ctrl + X is cut in the editor.
+    if !keyboard.just_pressed(KeyCode::KeyX)
+        || keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+    {
+        return;
+    }
+    let Ok(transform) = camera_q.single() else {
+        return;
+    };
+    let Some(hit) = world.raycast(transform.translation, *transform.forward(), 1024.0) else {
+        return;
+    };
+
+    let center = hit.pos.as_vec3() + 0.5;
+    history.begin(&world, "Explosion");
+    history.record_region(
+        &world,
+        (center - settings.radius).floor().as_ivec3(),
+        (center + settings.radius).ceil().as_ivec3(),
+    );
+    let explosion = world.explode(center, settings.radius, settings.power);
+    history.commit(&world);
+    let Some((min, max)) = explosion.bounds else {
+        return;
+    };
+    println!("Explosion removed {} voxels", explosion.removed.len());
+    edits.write(VoxelsEdited { min, max });
+    exploded.write(VoxelsExploded(explosion));
+}
+
+pub fn spawn_debris_particles(
+    mut commands: Commands,
+    mut exploded: EventReader<VoxelsExploded>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut cube: Local<Option<Handle<Mesh>>>,
+    world: Res<VoxelWorld>,
+    settings: Res<ExplosionSettings>,
+) {
+    for VoxelsExploded(explosion) in exploded.read() {
+        if settings.debris != DebrisMode::Particles {
+            continue;
+        }
+        let cube = cube
+            .get_or_insert_with(|| meshes.add(Cuboid::from_length(1.0)))
+            .clone();
+        let mut colors: HashMap<u8, Handle<StandardMaterial>> = HashMap::default();
+        let stride = explosion.removed.len().div_ceil(settings.max_debris.max(1));
+        for &(pos, mat_id) in explosion.removed.iter().step_by(stride) {
+            let material = colors.entry(mat_id).or_insert_with(|| {
+                let [r, g, b] = world.material(mat_id).color;
+                materials.add(StandardMaterial {
+                    base_color: Color::linear_rgb(r, g, b),
+                    ..default()
+                })
+            });
+            let point = pos.as_vec3() + 0.5;
+            let speed = settings.debris_speed * (0.5 + jitter(pos));
+            commands.spawn((
+                Mesh3d(cube.clone()),
+                MeshMaterial3d(material.clone()),
+                Transform::from_translation(point),
+                DebrisParticle {
+                    velocity: launch_velocity(explosion, point, speed),
+                    lifetime: settings.particle_lifetime * (0.5 + jitter(pos + IVec3::ONE)),
+                },
+            ));
+        }
+    }
+}
+
+pub fn spawn_debris_chunks(
+    mut commands: Commands,
+    mut exploded: EventReader<VoxelsExploded>,
+    world: Res<VoxelWorld>,
+    structural: Res<StructuralSettings>,
+    settings: Res<ExplosionSettings>,
+) {
+    for VoxelsExploded(explosion) in exploded.read() {
+        if settings.debris != DebrisMode::Chunks {
+            continue;
+        }
+        let size = settings.chunk_size.max(1);
+        let mut chunks: HashMap<IVec3, Vec<(IVec3, u8)>> = HashMap::default();
+        for &(pos, mat_id) in &explosion.removed {
+            chunks
+                .entry(pos.div_euclid(IVec3::splat(size)))
+                .or_default()
+                .push((pos, mat_id));
+        }
+        let mut keys: Vec<IVec3> = chunks.keys().copied().collect();
+        keys.sort_by_key(|p| (p.y, p.z, p.x));
+        let stride = keys.len().div_ceil(settings.max_debris.max(1));
+
+        for key in keys.into_iter().step_by(stride) {
+            let origin = key * size;
+            let mut volume = VoxelVolume::new(IVec3::splat(size));
+            for &(pos, mat_id) in &chunks[&key] {
+                volume.set(pos - origin, mat_id);
+            }
+            let Some(mut body) = VoxelBody::new(volume, &world, structural.voxel_size) else {
+                continue;
+            };
+            let point = origin.as_vec3() + body.center_of_mass;
+            body.linear_velocity = launch_velocity(
+                explosion,
+                point,
+                settings.debris_speed * (0.5 + jitter(key)),
+            );
+            commands.spawn(body_bundle(body, origin));
+        }
+    }
+}
+
+pub fn update_debris_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    world: Res<VoxelWorld>,
+    structural: Res<StructuralSettings>,
+    mut particles: Query<(Entity, &mut DebrisParticle, &mut Transform)>,
+) {
+    let dt = time.delta_secs();
+    let gravity = Vec3::NEG_Y * structural.gravity / structural.voxel_size;
+    for (entity, mut particle, mut transform) in &mut particles {
+        particle.lifetime -= dt;
+        if particle.lifetime <= 0.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        particle.velocity += gravity * dt;
+        let next = transform.translation + particle.velocity * dt;
+        let cell = next.floor().as_ivec3();
+        if cell.y < structural.ground_level || world.is_solid(cell) {
+            // Settle on whatever was hit and fade out there.
+            particle.velocity = Vec3::ZERO;
+        } else {
+            transform.translation = next;
+        }
+        transform.scale = Vec3::splat(particle.lifetime.min(1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Material;
+
+    const SOFT: u8 = 1;
+    const HARD: u8 = 2;
+
+    fn world(voxels: impl IntoIterator<Item = (IVec3, u8)>) -> VoxelWorld {
+        let mut world = VoxelWorld {
+            palette: vec![
+                Material::default(),
+                Material {
+                    yield_strength: 50.0,
+                    ..default()
+                },
+                Material {
+                    yield_strength: 1000.0,
+                    ..default()
+                },
+            ],
+            ..default()
+        };
+        for (pos, mat_id) in voxels {
+            world.set_voxel(pos, mat_id);
+        }
+        world
+    }
+
+    #[test]
+    fn pressure_falls_off_linearly_to_the_yield_strength() {
+        // 100 kPa at the center, 0 at 10 voxels: yield 50 breaks out to 5 voxels away.
+        let mut world = world((-12..=12).map(|x| (IVec3::new(x, 0, 0), SOFT)));
+        let explosion = world.explode(Vec3::splat(0.5), 10.0, 100.0);
+        let removed: Vec<i32> = explosion.removed.iter().map(|(p, _)| p.x).collect();
+        assert_eq!(removed, (-5..=5).collect::<Vec<_>>());
+        assert!(world.is_solid(IVec3::new(6, 0, 0)));
+        assert!(world.is_solid(IVec3::new(-6, 0, 0)));
+        assert!(!world.is_solid(IVec3::ZERO));
+    }
+
+    #[test]
+    fn materials_above_the_pressure_survive() {
+        let voxels = [
+            (IVec3::new(0, 0, 0), HARD),
+            (IVec3::new(1, 0, 0), SOFT),
+            (IVec3::new(0, 2, 0), SOFT),
+            (IVec3::new(0, 0, -3), HARD),
+        ];
+        let mut world = world(voxels);
+        let explosion = world.explode(Vec3::splat(0.5), 10.0, 100.0);
+        // Sorted by y, z, x.
+        let expected = [(IVec3::new(1, 0, 0), SOFT), (IVec3::new(0, 2, 0), SOFT)];
+        assert_eq!(explosion.removed, expected);
+        assert_eq!(
+            explosion.bounds,
+            Some((IVec3::new(0, 0, 0), IVec3::new(1, 2, 0)))
+        );
+        assert!(world.is_solid(IVec3::ZERO));
+        assert!(world.is_solid(IVec3::new(0, 0, -3)));
+        assert!(!world.is_solid(IVec3::new(1, 0, 0)));
+    }
+
+    #[test]
+    fn nothing_removed_has_no_bounds() {
+        let mut world = world([(IVec3::ZERO, HARD), (IVec3::new(30, 0, 0), SOFT)]);
+        let explosion = world.explode(Vec3::splat(0.5), 10.0, 100.0);
+        assert!(explosion.removed.is_empty());
+        assert_eq!(explosion.bounds, None);
+        assert!(world.is_solid(IVec3::ZERO));
+        assert!(world.is_solid(IVec3::new(30, 0, 0)));
+    }
+}
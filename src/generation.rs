@@ -0,0 +1,310 @@
+use crate::config::{Material, PATTERN_BRICKS, PATTERN_NOISE, PHASE_GRANULAR, PHASE_LIQUID};
+use crate::palette::Palette;
+use crate::thermal::{ThermalSettings, Transition, TransitionKind};
+use crate::voxel_map::VoxelWorld;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Palette of the demo scene, the same as `assets/default.palette.ron`.
+pub fn default_palette() -> Palette {
+    Palette::new([
+        ("air", Material::default()),
+        (
+            "red",
+            Material {
+                color: [1.0, 0.0, 0.0],
+                pattern: PATTERN_BRICKS,
+                pattern_scale: 8.0,
+                pattern_strength: 0.5,
+                ..default()
+            },
+        ),
+        (
+            "sand",
+            Material {
+                color: [0.76, 0.68, 0.45],
+                yield_strength: 5.0,
+                density: 1600.0,
+                friction: 0.6,
+                phase: PHASE_GRANULAR,
+                pattern: PATTERN_NOISE,
+                pattern_scale: 2.0,
+                pattern_strength: 0.25,
+                ..default()
+            },
+        ),
+        (
+            "water",
+            Material {
+                color: [0.15, 0.35, 0.75],
+                yield_strength: 0.0,
+                density: 1000.0,
+                friction: 0.05,
+                phase: PHASE_LIQUID,
+                ..default()
+            },
+        ),
+        (
+            "wood",
+            Material {
+                color: [0.45, 0.3, 0.15],
+                yield_strength: 40.0,
+                density: 700.0,
+                ignition_temperature: 300.0,
+                conductivity: 0.1,
+                texture_layer: 0,
+                ..default()
+            },
+        ),
+        (
+            "ice",
+            Material {
+                color: [0.75, 0.9, 1.0],
+                yield_strength: 20.0,
+                density: 920.0,
+                friction: 0.05,
+                melting_temperature: 0.0,
+                conductivity: 0.8,
+                ..default()
+            },
+        ),
+        (
+            "stone",
+            Material {
+                color: [0.45, 0.45, 0.48],
+                yield_strength: 200.0,
+                density: 2700.0,
+                melting_temperature: 1200.0,
+                conductivity: 0.3,
+                texture_layer: 1,
+                pattern: PATTERN_NOISE,
+                pattern_scale: 6.0,
+                pattern_strength: 0.2,
+                ..default()
+            },
+        ),
+        (
+            "lava",
+            Material {
+                color: [1.0, 0.35, 0.05],
+                yield_strength: 0.0,
+                density: 2600.0,
+                friction: 0.3,
+                phase: PHASE_LIQUID,
+                conductivity: 0.3,
+                ..default()
+            },
+        ),
+        (
+            "fire",
+            Material {
+                color: [1.0, 0.6, 0.1],
+                yield_strength: 0.0,
+                density: 1.0,
+                conductivity: 1.0,
+                ..default()
+            },
+        ),
+    ])
+}
+
+/// Fire and ice / water / stone / lava transitions, for the materials of that name in
+/// `palette`. Rules whose materials are missing are left out.
+pub fn default_thermal_settings(palette: &Palette) -> ThermalSettings {
+    let rule = |from: &str, to: &str, kind| {
+        Some(Transition {
+            from: palette.id(from)?,
+            to: palette.id(to)?,
+            kind,
+        })
+    };
+    ThermalSettings {
+        fire_material: palette.id("fire"),
+        transitions: [
+            rule("ice", "water", TransitionKind::Melt),
+            rule("water", "ice", TransitionKind::Freeze),
+            rule("stone", "lava", TransitionKind::Melt),
+            rule("lava", "stone", TransitionKind::Freeze),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        ..default()
+    }
+}
+
+/// How the world is filled on startup. Materials are palette names, resolved against the
+/// palette of the world being generated.
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WorldGenerator {
+    Empty,
+    Sphere {
+        center: IVec3,
+        radius: i32,
+        material: String,
+    },
+    /// Value noise heightmap covering `0..size` in x and z.
+    Terrain {
+        size: i32,
+        max_height: i32,
+        /// Width in voxels of one noise cell.
+        feature_size: i32,
+        seed: u32,
+        material: String,
+    },
+    /// `count` single voxels at random positions in `0..size` on every axis.
+    Scatter {
+        size: i32,
+        count: u32,
+        seed: u32,
+        material: String,
+    },
+}
+
+fn hash(x: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x27d4eb2d) ^ (z as u32).wrapping_mul(0x165667b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+/// Smoothly interpolated lattice noise in `0..1`.
+fn value_noise(x: f32, z: f32, seed: u32) -> f32 {
+    let (x0, z0) = (x.floor() as i32, z.floor() as i32);
+    let (fx, fz) = (x - x0 as f32, z - z0 as f32);
+    let (sx, sz) = (fx * fx * (3.0 - 2.0 * fx), fz * fz * (3.0 - 2.0 * fz));
+    let corner = |dx, dz| hash(x0 + dx, z0 + dz, seed) as f32 / u32::MAX as f32;
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * sx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * sx;
+    top + (bottom - top) * sz
+}
+
+impl Default for WorldGenerator {
+    fn default() -> Self {
+        Self::Sphere {
+            center: IVec3::splat(32),
+            radius: 32,
+            material: "red".to_string(),
+        }
+    }
+}
+
+impl WorldGenerator {
+    pub fn generate(&self, world: &mut VoxelWorld) {
+        let material = match self {
+            Self::Empty => return,
+            Self::Sphere { material, .. }
+            | Self::Terrain { material, .. }
+            | Self::Scatter { material, .. } => material,
+        };
+        let Some(material) = world.material_id(material) else {
+            println!("Generator material {:?} is not in the palette", material);
+            return;
+        };
+        match *self {
+            Self::Empty => {}
+            Self::Sphere { center, radius, .. } => {
+                for x in -radius..=radius {
+                    for y in -radius..=radius {
+                        for z in -radius..=radius {
+                            let offset = IVec3::new(x, y, z);
+                            if offset.length_squared() <= (radius * radius) {
+                                world.set_voxel(center + offset, material);
+                            }
+                        }
+                    }
+                }
+            }
+            Self::Terrain {
+                size,
+                max_height,
+                feature_size,
+                seed,
+                ..
+            } => {
+                let cell = feature_size.max(1) as f32;
+                for x in 0..size {
+                    for z in 0..size {
+                        // Two octaves, the second adds detail at half the feature size.
+                        let (nx, nz) = (x as f32 / cell, z as f32 / cell);
+                        let noise = value_noise(nx, nz, seed) * 0.7
+                            + value_noise(nx * 2.0, nz * 2.0, seed.wrapping_add(1)) * 0.3;
+                        let height = (noise * max_height as f32) as i32;
+                        for y in 0..=height {
+                            world.set_voxel(IVec3::new(x, y, z), material);
+                        }
+                    }
+                }
+            }
+            Self::Scatter {
+                size, count, seed, ..
+            } => {
+                for i in 0..count as i32 {
+                    let pos = IVec3::new(
+                        hash(i, 0, seed) as i32,
+                        hash(i, 1, seed) as i32,
+                        hash(i, 2, seed) as i32,
+                    )
+                    .rem_euclid(IVec3::splat(size.max(1)));
+                    world.set_voxel(pos, material);
+                }
+            }
+        }
+    }
+}
+
+pub fn generate_world(generator: Res<WorldGenerator>, mut world: ResMut<VoxelWorld>) {
+    generator.generate(&mut world);
+    println!("World generated: {:?}", *generator);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world_config::WorldConfig;
+
+    fn world() -> VoxelWorld {
+        let mut world = VoxelWorld::default();
+        world.set_palette(&default_palette());
+        world
+    }
+
+    #[test]
+    fn generator_materials_are_palette_names() {
+        let mut world = world();
+        let generator = WorldGenerator::Sphere {
+            center: IVec3::ZERO,
+            radius: 2,
+            material: "stone".to_string(),
+        };
+        generator.generate(&mut world);
+        assert_eq!(
+            world.get_voxel(IVec3::ZERO),
+            default_palette().id("stone").unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_generator_material_generates_nothing() {
+        let mut world = world();
+        let generator = WorldGenerator::Scatter {
+            size: 16,
+            count: 10,
+            seed: 1,
+            material: "unobtainium".to_string(),
+        };
+        generator.generate(&mut world);
+        assert!(world.sectors.is_empty());
+    }
+
+    #[test]
+    fn settings_file_generator_resolves() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/settings.ron");
+        let config = WorldConfig::from_file(path).unwrap();
+        let mut world = world();
+        config.generator.generate(&mut world);
+        assert!(!world.sectors.is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+use crate::config::Brick;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited, voxel_index};
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+/// Contents of one brick around an edit, `None` where the brick did not exist.
+#[derive(Clone, Copy, Debug)]
+struct BrickChange {
+    /// Brick coordinates, i.e. voxel position >> 2.
+    key: IVec3,
+    before: Option<Brick>,
+    after: Option<Brick>,
+}
+
+/// One undo step.
+#[derive(Clone, Debug)]
+pub struct EditGroup {
+    pub label: String,
+    changes: Vec<BrickChange>,
+}
+
+impl EditGroup {
+    fn bytes(&self) -> usize {
+        self.changes.len() * size_of::<BrickChange>() + self.label.len()
+    }
+
+    fn bounds(&self) -> (IVec3, IVec3) {
+        let min = self
+            .changes
+            .iter()
+            .map(|c| c.key << 2)
+            .fold(IVec3::MAX, IVec3::min);
+        let max = self
+            .changes
+            .iter()
+            .map(|c| (c.key << 2) + 3)
+            .fold(IVec3::MIN, IVec3::max);
+        (min, max)
+    }
+
+    /// Reverts (or redoes) the voxels the step changed. Voxels that changed again since are
+    /// left alone, so sand, water and heat moving through the bricks later is not undone.
+    fn apply(&self, world: &mut VoxelWorld, undo: bool) {
+        let empty = Brick { voxels: [0; 64] };
+        for change in &self.changes {
+            let (from, to) = if undo {
+                (change.after, change.before)
+            } else {
+                (change.before, change.after)
+            };
+            let (from, to) = (from.unwrap_or(empty), to.unwrap_or(empty));
+            let origin = change.key << 2;
+            for i in 0..64 {
+                let pos =
+                    origin + IVec3::new(i as i32 & 3, (i as i32 >> 4) & 3, (i as i32 >> 2) & 3);
+                if from.voxels[i] != to.voxels[i] && world.get_voxel(pos) == from.voxels[i] {
+                    world.set_voxel(pos, to.voxels[i]);
+                }
+            }
+        }
+    }
+}
+
+/// Transaction log of voxel edits. Edits made between `begin` and `commit` through
+/// `set_voxel` (or after `record_region`) form one undo step. Only the bricks touched are
+/// stored, and the oldest steps are dropped once `max_bytes` is exceeded.
+///
+/// Undo only reverts voxels that still hold what the step left there. Changes that follow
+/// from a step, like a structure collapsing, are added to it with `amend`.
+#[derive(Resource)]
+pub struct EditHistory {
+    pub max_bytes: usize,
+    undo: VecDeque<EditGroup>,
+    redo: Vec<EditGroup>,
+    bytes: usize,
+    open: Option<(String, HashMap<IVec3, Option<Brick>>)>,
+    /// Whether the last step was committed this frame and may still be amended.
+    amendable: bool,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            bytes: 0,
+            open: None,
+            amendable: false,
+        }
+    }
+}
+
+impl EditHistory {
+    /// Starts a new undo step, committing any step still open.
+    pub fn begin(&mut self, world: &VoxelWorld, label: impl Into<String>) {
+        self.commit(world);
+        self.open = Some((label.into(), HashMap::default()));
+    }
+
+    /// Remembers the brick containing `pos` before it is first modified in the open step.
+    pub fn record(&mut self, world: &VoxelWorld, pos: IVec3) {
+        let Some((_, before)) = self.open.as_mut() else {
+            return;
+        };
+        before
+            .entry(pos >> 2)
+            .or_insert_with(|| world.get_brick_at(pos).copied());
+    }
+
+    /// Records every brick in `min..=max`, for operations that edit the world themselves.
+    pub fn record_region(&mut self, world: &VoxelWorld, min: IVec3, max: IVec3) {
+        let (lo, hi): (IVec3, IVec3) = (min >> 2, max >> 2);
+        for z in lo.z..=hi.z {
+            for y in lo.y..=hi.y {
+                for x in lo.x..=hi.x {
+                    self.record(world, IVec3::new(x, y, z) << 2);
+                }
+            }
+        }
+    }
+
+    pub fn set_voxel(&mut self, world: &mut VoxelWorld, pos: IVec3, mat_id: u8) -> u8 {
+        self.record(world, pos);
+        world.set_voxel(pos, mat_id)
+    }
+
+    /// Closes the open step. Steps that changed nothing are discarded.
+    pub fn commit(&mut self, world: &VoxelWorld) {
+        let Some((label, before)) = self.open.take() else {
+            return;
+        };
+        let mut changes: Vec<BrickChange> = before
+            .into_iter()
+            .map(|(key, before)| BrickChange {
+                key,
+                before,
+                after: world.get_brick_at(key << 2).copied(),
+            })
+            .filter(|c| c.before != c.after)
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        changes.sort_by_key(|c| (c.key.y, c.key.z, c.key.x));
+
+        let group = EditGroup { label, changes };
+        self.bytes += group.bytes();
+        self.undo.push_back(group);
+        self.redo.clear();
+        self.amendable = true;
+        while self.bytes > self.max_bytes && self.undo.len() > 1 {
+            if let Some(dropped) = self.undo.pop_front() {
+                self.bytes -= dropped.bytes();
+            }
+        }
+    }
+
+    /// Adds voxels that changed as a consequence of the last step, such as a structure that
+    /// collapsed after an edit, to that step. `changes` are the positions and previous
+    /// materials of voxels already changed in `world`. Does nothing after `seal`.
+    pub fn amend(&mut self, world: &VoxelWorld, changes: &[(IVec3, u8)]) {
+        if !self.amendable || self.open.is_some() {
+            return;
+        }
+        let Some(group) = self.undo.back_mut() else {
+            return;
+        };
+        self.bytes -= group.bytes();
+        let mut added: HashSet<IVec3> = HashSet::default();
+        for &(pos, previous) in changes {
+            let key = pos >> 2;
+            let index = match group.changes.iter().position(|c| c.key == key) {
+                Some(index) => index,
+                None => {
+                    group.changes.push(BrickChange {
+                        key,
+                        before: world.get_brick_at(pos).copied(),
+                        after: None,
+                    });
+                    added.insert(key);
+                    group.changes.len() - 1
+                }
+            };
+            let change = &mut group.changes[index];
+            // Bricks the step already touched have their state from before the step.
+            if added.contains(&key) {
+                change
+                    .before
+                    .get_or_insert(Brick { voxels: [0; 64] })
+                    .voxels[voxel_index(pos)] = previous;
+            }
+            change.after = world.get_brick_at(pos).copied();
+        }
+        group.changes.sort_by_key(|c| (c.key.y, c.key.z, c.key.x));
+        self.bytes += group.bytes();
+    }
+
+    /// Ends the frame in which the last step could be amended.
+    pub fn seal(&mut self) {
+        self.amendable = false;
+    }
+
+    /// Drops every step, for when the world is replaced wholesale.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.bytes = 0;
+        self.open = None;
+        self.amendable = false;
+    }
+
+    /// Reverts the last step and returns its label and the region it covered.
+    pub fn undo(&mut self, world: &mut VoxelWorld) -> Option<(String, IVec3, IVec3)> {
+        self.commit(world);
+        let group = self.undo.pop_back()?;
+        self.amendable = false;
+        self.bytes -= group.bytes();
+        group.apply(world, true);
+        let (min, max) = group.bounds();
+        let label = group.label.clone();
+        self.redo.push(group);
+        Some((label, min, max))
+    }
+
+    pub fn redo(&mut self, world: &mut VoxelWorld) -> Option<(String, IVec3, IVec3)> {
+        self.commit(world);
+        let group = self.redo.pop()?;
+        self.amendable = false;
+        group.apply(world, false);
+        let (min, max) = group.bounds();
+        let label = group.label.clone();
+        self.bytes += group.bytes();
+        self.undo.push_back(group);
+        Some((label, min, max))
+    }
+}
+
+/// Ctrl + Z undoes, Ctrl + Y or Ctrl + Shift + Z redoes.
+pub fn undo_redo_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<EditHistory>,
+    mut world: ResMut<VoxelWorld>,
+    mut edits: EventWriter<VoxelsEdited>,
+) {
+    if !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let result = if keyboard.just_pressed(KeyCode::KeyZ) && !shift {
+        history.undo(&mut world).map(|r| ("Undo", r))
+    } else if keyboard.just_pressed(KeyCode::KeyY)
+        || (keyboard.just_pressed(KeyCode::KeyZ) && shift)
+    {
+        history.redo(&mut world).map(|r| ("Redo", r))
+    } else {
+        return;
+    };
+    if let Some((action, (label, min, max))) = result {
+        println!("{}: {}", action, label);
+        edits.write(VoxelsEdited { min, max });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: u8 = 1;
+    const SAND: u8 = 2;
+
+    fn edit(history: &mut EditHistory, world: &mut VoxelWorld, voxels: &[(IVec3, u8)]) {
+        history.begin(world, "Edit");
+        for &(pos, mat_id) in voxels {
+            history.set_voxel(world, pos, mat_id);
+        }
+        history.commit(world);
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let (mut world, mut history) = (VoxelWorld::default(), EditHistory::default());
+        world.set_voxel(IVec3::new(5, 0, 0), STONE);
+        let voxels = [(IVec3::ZERO, STONE), (IVec3::new(5, 0, 0), 0)];
+        edit(&mut history, &mut world, &voxels);
+
+        let (label, min, max) = history.undo(&mut world).unwrap();
+        assert_eq!(label, "Edit");
+        assert_eq!((min, max), (IVec3::ZERO, IVec3::new(7, 3, 3)));
+        assert!(!world.is_solid(IVec3::ZERO));
+        assert!(world.is_solid(IVec3::new(5, 0, 0)));
+        // The brick left empty by the undo is gone again.
+        assert!(world.get_brick_at(IVec3::ZERO).is_none());
+
+        history.redo(&mut world).unwrap();
+        assert!(world.is_solid(IVec3::ZERO));
+        assert!(!world.is_solid(IVec3::new(5, 0, 0)));
+        assert!(history.redo(&mut world).is_none());
+    }
+
+    #[test]
+    fn edits_between_begin_and_commit_are_one_step() {
+        let (mut world, mut history) = (VoxelWorld::default(), EditHistory::default());
+        edit(
+            &mut history,
+            &mut world,
+            &[(IVec3::ZERO, STONE), (IVec3::X * 9, STONE)],
+        );
+        edit(&mut history, &mut world, &[(IVec3::Y * 9, STONE)]);
+        // Steps that change nothing are not kept.
+        edit(&mut history, &mut world, &[(IVec3::Y * 9, STONE)]);
+
+        history.undo(&mut world).unwrap();
+        assert!(!world.is_solid(IVec3::Y * 9));
+        assert!(world.is_solid(IVec3::ZERO) && world.is_solid(IVec3::X * 9));
+        history.undo(&mut world).unwrap();
+        assert!(world.sectors.is_empty());
+        assert!(history.undo(&mut world).is_none());
+    }
+
+    #[test]
+    fn a_new_step_clears_redo() {
+        let (mut world, mut history) = (VoxelWorld::default(), EditHistory::default());
+        edit(&mut history, &mut world, &[(IVec3::ZERO, STONE)]);
+        history.undo(&mut world).unwrap();
+        edit(&mut history, &mut world, &[(IVec3::X, STONE)]);
+        assert!(history.redo(&mut world).is_none());
+        assert!(!world.is_solid(IVec3::ZERO));
+    }
+
+    #[test]
+    fn oldest_steps_are_dropped_over_max_bytes() {
+        let (mut world, mut history) = (VoxelWorld::default(), EditHistory::default());
+        // Each single brick step takes the same space, room for two of them.
+        edit(&mut history, &mut world, &[(IVec3::ZERO, STONE)]);
+        history.max_bytes = 2 * history.bytes;
+        for x in 1..=3 {
+            edit(&mut history, &mut world, &[(IVec3::X * 8 * x, STONE)]);
+        }
+        assert_eq!(history.undo.len(), 2);
+        assert!(history.bytes <= history.max_bytes);
+        history.undo(&mut world).unwrap();
+        history.undo(&mut world).unwrap();
+        assert!(history.undo(&mut world).is_none());
+        assert!(world.is_solid(IVec3::ZERO) && world.is_solid(IVec3::X * 8));
+    }
+
+    #[test]
+    fn undo_keeps_later_simulation_changes() {
+        let (mut world, mut history) = (VoxelWorld::default(), EditHistory::default());
+        edit(
+            &mut history,
+            &mut world,
+            &[(IVec3::ZERO, STONE), (IVec3::Y * 3, SAND)],
+        );
+        // Sand falls inside the brick and more sand arrives after the edit.
+        world.set_voxel(IVec3::Y * 3, 0);
+        world.set_voxel(IVec3::Y, SAND);
+        world.set_voxel(IVec3::X, SAND);
+
+        history.undo(&mut world).unwrap();
+        assert!(!world.is_solid(IVec3::ZERO));
+        assert_eq!(world.get_voxel(IVec3::Y), SAND);
+        assert_eq!(world.get_voxel(IVec3::X), SAND);
+        assert_eq!(world.get_voxel(IVec3::Y * 3), 0);
+    }
+
+    #[test]
+    fn collapses_after_an_edit_are_part_of_its_step() {
+        let (mut world, mut history) = (VoxelWorld::default(), EditHistory::default());
+        for y in 0..12 {
+            world.set_voxel(IVec3::Y * y, STONE);
+        }
+        edit(&mut history, &mut world, &[(IVec3::Y * 2, 0)]);
+        // What the structural solver does with the part above the cut.
+        let island: Vec<(IVec3, u8)> = (3..12).map(|y| (IVec3::Y * y, STONE)).collect();
+        for &(pos, _) in &island {
+            world.set_voxel(pos, 0);
+        }
+        history.amend(&world, &island);
+        history.seal();
+        world.set_voxel(IVec3::Y * 20, STONE);
+        history.amend(&world, &[(IVec3::Y * 20, 0)]);
+
+        history.undo(&mut world).unwrap();
+        assert!((0..12).all(|y| world.is_solid(IVec3::Y * y)));
+        assert!(
+            world.is_solid(IVec3::Y * 20),
+            "changes after seal are not amended"
+        );
+        history.redo(&mut world).unwrap();
+        assert!((2..12).all(|y| !world.is_solid(IVec3::Y * y)));
+    }
+}
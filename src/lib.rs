@@ -0,0 +1,36 @@
+pub mod benchmark;
+pub mod camera_path;
+pub mod capture;
+pub mod cellular;
+pub mod character;
+pub mod collision;
+pub mod compute;
+pub mod config;
+pub mod editor;
+pub mod explosion;
+pub mod generation;
+pub mod history;
+pub mod palette;
+pub mod plugin;
+pub mod prefab;
+pub mod render;
+pub mod rigid_body;
+pub mod structure;
+pub mod surface;
+pub mod thermal;
+pub mod volume;
+pub mod voxel_map;
+pub mod world_config;
+
+pub use crate::benchmark::Benchmark;
+pub use crate::camera_path::{CameraKeyframe, CameraPath};
+pub use crate::capture::{CaptureFrame, CaptureSettings};
+pub use crate::compute::WriteTextureWorker;
+pub use crate::config::{AppSettings, Brick, Material};
+pub use crate::generation::WorldGenerator;
+pub use crate::palette::{Palette, PaletteEntry};
+pub use crate::plugin::{
+    VoxelCorePlugin, VoxelInputPlugin, VoxelPlugin, VoxelRenderPlugin, VoxelSet,
+};
+pub use crate::voxel_map::{SvoStorage, VoxelWorld, VoxelsEdited};
+pub use crate::world_config::{CONFIG_FILE, WorldConfig};
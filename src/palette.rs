@@ -0,0 +1,292 @@
+use crate::config::Material;
+use crate::editor::EditorState;
+use crate::history::EditHistory;
+use crate::prefab::Clipboard;
+use crate::surface::MAX_TEXTURE_LAYERS;
+use crate::thermal::ThermalSettings;
+use crate::voxel_map::VoxelWorld;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PaletteEntry {
+    pub name: String,
+    #[serde(default)]
+    pub material: Material,
+}
+
+/// Named materials, the index of an entry is its material id. Entry 0 is air.
+/// Saved voxels refer to materials by id, so anything storing them keeps the palette names
+/// around and remaps with `Palette::remap_from` when the palette changes.
+#[derive(Asset, TypePath, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Palette {
+    pub entries: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    pub fn new(entries: impl IntoIterator<Item = (&'static str, Material)>) -> Self {
+        Self {
+            entries: entries
+                .into_iter()
+                .map(|(name, material)| PaletteEntry {
+                    name: name.to_string(),
+                    material,
+                })
+                .collect(),
+        }
+    }
+
+    /// Material id of `name`.
+    pub fn id(&self, name: &str) -> Option<u8> {
+        self.entries
+            .iter()
+            .position(|e| e.name == name)
+            .and_then(|id| u8::try_from(id).ok())
+    }
+
+    pub fn materials(&self) -> Vec<Material> {
+        self.entries.iter().map(|e| e.material).collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.entries.iter().map(|e| e.name.clone()).collect()
+    }
+
+    /// For every id of a palette with `old_names`, the id of the same material here.
+    pub fn remap_from(&self, old_names: &[String]) -> Vec<u8> {
+        remap_names(old_names, &self.names())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let palette: Self = ron::de::from_bytes(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if palette.entries.len() > 256 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "palette has more than 256 materials",
+            ));
+        }
+        if let Some(entry) = palette
+            .entries
+            .iter()
+            .find(|e| e.material.texture_layer >= MAX_TEXTURE_LAYERS as i32)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "material {:?} uses texture layer {}, the atlas has at most {}",
+                    entry.name, entry.material.texture_layer, MAX_TEXTURE_LAYERS
+                ),
+            ));
+        }
+        // Ids are remapped by name, a repeated name would be ambiguous.
+        let mut names = HashSet::new();
+        if let Some(entry) = palette.entries.iter().find(|e| !names.insert(&e.name)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("palette has two materials named {:?}", entry.name),
+            ));
+        }
+        Ok(palette)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Id remap table from a palette named `old` to one named `new`, matching by name.
+/// Materials that no longer exist become air, and without `old` names ids are kept.
+pub fn remap_names(old: &[String], new: &[String]) -> Vec<u8> {
+    if old.is_empty() {
+        return (0..=255).collect();
+    }
+    (0..256)
+        .map(|id| match old.get(id) {
+            Some(name) if id != 0 => new
+                .iter()
+                .position(|n| n == name)
+                .and_then(|id| u8::try_from(id).ok())
+                .unwrap_or(0),
+            _ => 0,
+        })
+        .collect()
+}
+
+/// True if `map` leaves every id where it is.
+pub fn is_identity(map: &[u8]) -> bool {
+    map.iter().enumerate().all(|(id, &to)| id == to as usize)
+}
+
+impl VoxelWorld {
+    pub fn material_id(&self, name: &str) -> Option<u8> {
+        self.palette_names
+            .iter()
+            .position(|n| n == name)
+            .and_then(|id| u8::try_from(id).ok())
+    }
+
+    /// Switches to `palette`, rewriting every voxel so it keeps its material by name.
+    /// Returns the id remap table, which is the identity for a world without names.
+    pub fn set_palette(&mut self, palette: &Palette) -> Vec<u8> {
+        let map = palette.remap_from(&self.palette_names);
+        if !is_identity(&map) {
+            let missing: Vec<&String> = self
+                .palette_names
+                .iter()
+                .enumerate()
+                .filter(|&(id, _)| id != 0 && map[id] == 0)
+                .map(|(_, name)| name)
+                .collect();
+            if !missing.is_empty() {
+                println!(
+                    "Materials missing from the new palette turn into air: {:?}",
+                    missing
+                );
+            }
+            for sector in self.sectors.values_mut() {
+                let mut emptied = Vec::new();
+                for (&brick_idx, brick) in sector.bricks.iter_mut() {
+                    for voxel in &mut brick.voxels {
+                        *voxel = map[*voxel as usize];
+                    }
+                    if brick.pack_bits_64() == 0 {
+                        emptied.push(brick_idx);
+                    }
+                }
+                for brick_idx in emptied {
+                    sector.remove_brick(brick_idx);
+                }
+            }
+            self.sectors.retain(|_, sector| !sector.bricks.is_empty());
+        }
+        self.palette = palette.materials();
+        self.palette_names = palette.names();
+        map
+    }
+}
+
+#[derive(Default)]
+pub struct PaletteLoader;
+
+impl AssetLoader for PaletteLoader {
+    type Asset = Palette;
+    type Settings = ();
+    type Error = io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Palette, io::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Palette::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["palette.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct PaletteHandle(pub Handle<Palette>);
+
+/// Applies the palette asset when it is loaded and whenever the file changes, remapping the
+/// world and every other holder of material ids.
+pub fn apply_palette(
+    mut events: EventReader<AssetEvent<Palette>>,
+    handle: Res<PaletteHandle>,
+    palettes: Res<Assets<Palette>>,
+    mut world: ResMut<VoxelWorld>,
+    mut thermal: ResMut<ThermalSettings>,
+    mut history: ResMut<EditHistory>,
+    editor: Option<ResMut<EditorState>>,
+    mut clipboard: Option<ResMut<Clipboard>>,
+) {
+    let reloaded = events
+        .read()
+        .filter(|e| e.is_loaded_with_dependencies(&handle.0) || e.is_modified(&handle.0))
+        .count()
+        > 0;
+    let Some(palette) = palettes.get(&handle.0).filter(|_| reloaded) else {
+        return;
+    };
+    if world.palette == palette.materials() && world.palette_names == palette.names() {
+        return;
+    }
+    let map = world.set_palette(palette);
+    thermal.remap(&map);
+    if let Some(mut editor) = editor {
+        editor.material = map[editor.material as usize].max(1);
+    }
+    if let Some(volume) = clipboard.as_deref_mut().and_then(|c| c.volume.as_mut()) {
+        volume.remap(&map);
+    }
+    if !is_identity(&map) {
+        // Undo steps hold whole bricks with the old ids.
+        history.clear();
+    }
+    println!("Palette applied: {} materials", palette.entries.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn remap_follows_reordered_names() {
+        let old = names(&["air", "stone", "wood", "sand"]);
+        let new = names(&["air", "sand", "stone", "wood"]);
+        let map = remap_names(&old, &new);
+        assert_eq!(map.len(), 256);
+        assert_eq!(map[..4], [0, 2, 3, 1]);
+        assert!(!is_identity(&map));
+    }
+
+    #[test]
+    fn removed_materials_become_air() {
+        let old = names(&["air", "stone", "wood"]);
+        let new = names(&["air", "wood"]);
+        let map = remap_names(&old, &new);
+        assert_eq!(map[..3], [0, 0, 1]);
+        // Ids past the old palette have nothing to match.
+        assert!(map[3..].iter().all(|&id| id == 0));
+    }
+
+    #[test]
+    fn remap_without_old_names_keeps_ids() {
+        let map = remap_names(&[], &names(&["air", "stone"]));
+        assert!(is_identity(&map));
+        assert_eq!(map.len(), 256);
+    }
+
+    #[test]
+    fn texture_layers_past_the_atlas_are_rejected() {
+        let ron = br#"[(name: "air"), (name: "stone", material: (texture_layer: 64))]"#;
+        assert!(Palette::from_bytes(ron).is_err());
+        let ron = br#"[(name: "air"), (name: "stone", material: (texture_layer: 63))]"#;
+        assert!(Palette::from_bytes(ron).is_ok());
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let ron = br#"[(name: "air"), (name: "stone"), (name: "stone")]"#;
+        let err = Palette::from_bytes(ron).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("stone"), "{}", err);
+        let ron = br#"[(name: "air"), (name: "stone")]"#;
+        assert_eq!(Palette::from_bytes(ron).unwrap().id("stone"), Some(1));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::benchmark::{Benchmark, benchmark_input, not_benchmarking, run_benchmark};
+use crate::camera_path::{CameraPathPlayer, camera_path_input, update_camera_path};
+use crate::capture::{
+    Capture, CaptureFrame, CaptureSettings, capture_input, screenshot_input, take_captures,
+};
+use crate::cellular::{
+    ActiveBricks, CellularSettings, activate_edited_bricks, pour_at_cursor, simulate_cellular,
+};
+use crate::character::{
+    CameraMode, CameraSettings, camera_movement_system, character_controller_system,
+    toggle_camera_mode,
+};
+use crate::compute::{WriteTextureWorker, handle_compute_params, upload_to_gpu};
+use crate::config::{
+    AppSettings, AtmosphereSettings, LevelOfDetail, RenderDebugMode, TemporalAntiAliasing,
+};
+use crate::editor::{
+    EditorState, apply_editor_tool, draw_brush_preview, editor_input, setup_editor_ui,
+    toggle_editor, update_palette_picker,
+};
+use crate::explosion::{
+    ExplosionSettings, VoxelsExploded, cycle_debris_mode, explode_at_cursor, spawn_debris_chunks,
+    spawn_debris_particles, update_debris_particles,
+};
+use crate::generation::{
+    WorldGenerator, default_palette, default_thermal_settings, generate_world,
+};
+use crate::history::{EditHistory, undo_redo_system};
+use crate::palette::Palette;
+use crate::prefab::{Clipboard, Prefab, PrefabLoader, clipboard_input, draw_clipboard_preview};
+use crate::render::*;
+use crate::rigid_body::{
+    RigidBodySettings, attach_body_meshes, spawn_rigid_bodies, step_rigid_bodies,
+};
+use crate::structure::{IslandDetached, StructuralSettings, collapse_islands, solve_structures};
+use crate::surface::{load_surface_textures, upload_surface_textures};
+use crate::thermal::{
+    ThermalField, ThermalSettings, activate_edited_fire, heat_at_cursor, simulate_thermal,
+};
+use crate::voxel_map::{SECTOR_SCALE, SvoStorage, VoxelWorld, VoxelsEdited, rebuild_svo};
+use crate::world_config::{VoxelConfigPlugin, WorldConfig};
+use bevy::pbr::PreparedMaterial;
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResourcePlugin;
+use bevy::render::render_asset::prepare_assets;
+use bevy::render::texture::GpuImage;
+use bevy::render::{Render, RenderApp, RenderSet};
+use bevy_app_compute::prelude::*;
+
+/// Everything needed to run the engine: the core plus, unless `headless`, rendering and
+/// input. Expects `DefaultPlugins` (or `MinimalPlugins` when headless) to be added first.
+pub struct VoxelPlugin {
+    pub settings: AppSettings,
+    pub palette: Palette,
+    pub generator: WorldGenerator,
+    /// Burning and melting rules, material ids refer to `palette`. When `None` the demo rules
+    /// are set up for whichever of its materials `palette` has, by name.
+    pub thermal: Option<ThermalSettings>,
+    pub camera: CameraSettings,
+    /// Asset path of a `WorldConfig` that is applied once loaded and on every change.
+    /// Needs the asset server, so it is ignored when `headless`.
+    pub config_path: Option<String>,
+    pub headless: bool,
+}
+
+impl Default for VoxelPlugin {
+    fn default() -> Self {
+        Self {
+            settings: AppSettings::default(),
+            palette: default_palette(),
+            generator: WorldGenerator::default(),
+            thermal: None,
+            camera: CameraSettings::default(),
+            config_path: None,
+            headless: false,
+        }
+    }
+}
+
+impl VoxelPlugin {
+    /// Starts from `config` instead of the defaults. The palette it names is loaded by
+    /// `VoxelConfigPlugin`, set `palette` as well to have it from the first frame.
+    pub fn with_config(self, config: WorldConfig) -> Self {
+        Self {
+            settings: config.settings,
+            generator: config.generator,
+            camera: config.camera,
+            ..self
+        }
+    }
+}
+
+impl Plugin for VoxelPlugin {
+    fn build(&self, app: &mut App) {
+        let mut world = VoxelWorld::default();
+        world.set_palette(&self.palette);
+        let thermal = self
+            .thermal
+            .clone()
+            .unwrap_or_else(|| default_thermal_settings(&self.palette));
+        app.insert_resource(self.settings)
+            .insert_resource(world)
+            .insert_resource(self.generator.clone())
+            .insert_resource(thermal)
+            .insert_resource(self.camera)
+            .add_plugins(VoxelCorePlugin);
+        if !self.headless {
+            app.add_plugins((VoxelRenderPlugin, VoxelInputPlugin));
+            if let Some(path) = &self.config_path {
+                app.add_plugins(VoxelConfigPlugin { path: path.clone() });
+            }
+        }
+    }
+}
+
+/// Frame phases in `Update`, in order.
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoxelSet {
+    /// Camera and character movement.
+    Input,
+    /// Player edits to the world.
+    Edit,
+    /// Structures, rigid bodies, heat and cellular flow. Paused while a `Benchmark` runs.
+    Simulate,
+    /// SVO rebuild from the edited world.
+    Rebuild,
+    /// GPU uploads and render settings.
+    Render,
+}
+
+/// World storage, generation and simulation. Runs under `MinimalPlugins`, so it can be used
+/// by tests and dedicated servers without a window or GPU. Without a `VoxelWorld` resource the
+/// world starts with `default_palette`, which the generator's material names resolve against.
+pub struct VoxelCorePlugin;
+
+impl Plugin for VoxelCorePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<VoxelWorld>() {
+            let mut world = VoxelWorld::default();
+            world.set_palette(&default_palette());
+            app.insert_resource(world);
+        }
+        app.init_resource::<StructuralSettings>()
+            .insert_resource(SvoStorage {
+                tree_scale: SECTOR_SCALE as u32,
+                ..default()
+            })
+            .init_resource::<RigidBodySettings>()
+            .init_resource::<ExplosionSettings>()
+            .init_resource::<CellularSettings>()
+            .init_resource::<ActiveBricks>()
+            .init_resource::<ThermalField>()
+            .init_resource::<ThermalSettings>()
+            .init_resource::<EditHistory>()
+            .init_resource::<WorldGenerator>()
+            .add_event::<VoxelsEdited>()
+            .add_event::<IslandDetached>()
+            .add_event::<VoxelsExploded>()
+            .configure_sets(
+                Update,
+                (
+                    VoxelSet::Input,
+                    VoxelSet::Edit,
+                    VoxelSet::Simulate,
+                    VoxelSet::Rebuild,
+                    VoxelSet::Render,
+                )
+                    .chain(),
+            )
+            .configure_sets(Update, VoxelSet::Simulate.run_if(not_benchmarking))
+            .add_systems(Startup, generate_world)
+            .add_systems(
+                Update,
+                (
+                    solve_structures,
+                    collapse_islands,
+                    spawn_rigid_bodies,
+                    step_rigid_bodies,
+                    spawn_debris_chunks,
+                    activate_edited_fire,
+                    simulate_thermal,
+                    activate_edited_bricks,
+                    simulate_cellular,
+                )
+                    .chain()
+                    .in_set(VoxelSet::Simulate),
+            )
+            .add_systems(Update, rebuild_svo.in_set(VoxelSet::Rebuild));
+    }
+}
+
+/// Compute ray marcher, composite pass and the meshes of dynamic voxel entities.
+/// Needs `DefaultPlugins` and `AppSettings`.
+pub struct VoxelRenderPlugin;
+
+impl Plugin for VoxelRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RenderDebugMode>()
+            .init_resource::<TemporalAntiAliasing>()
+            .init_resource::<LevelOfDetail>()
+            .init_resource::<AtmosphereSettings>()
+            .init_resource::<RenderResolution>()
+            .init_resource::<CaptureSettings>()
+            .init_resource::<CameraPathPlayer>()
+            .init_resource::<Benchmark>()
+            .init_resource::<Capture>()
+            .add_event::<CaptureFrame>()
+            .add_plugins(AppComputePlugin)
+            .add_plugins(AppComputeWorkerPlugin::<WriteTextureWorker>::default())
+            .add_plugins(MaterialPlugin::<VoxelCompositeMaterial>::default())
+            .add_plugins((
+                ExtractResourcePlugin::<DisplayImage>::default(),
+                ExtractResourcePlugin::<DepthImage>::default(),
+                ExtractResourcePlugin::<ComputeTransfer>::default(),
+            ))
+            .add_systems(Startup, (setup, load_surface_textures))
+            .add_systems(
+                Update,
+                (
+                    attach_body_meshes,
+                    spawn_debris_particles,
+                    update_debris_particles,
+                )
+                    .in_set(VoxelSet::Simulate)
+                    .after(spawn_debris_chunks),
+            )
+            .add_systems(
+                Update,
+                (
+                    cycle_render_debug_mode,
+                    adjust_render_scale,
+                    toggle_taa,
+                    toggle_lod,
+                    sync_sun_light,
+                    handle_resize,
+                    update_composite_params,
+                    upload_to_gpu,
+                    upload_surface_textures,
+                    benchmark_input,
+                    run_benchmark,
+                    handle_compute_params,
+                    extract_compute_view,
+                    screenshot_input,
+                    capture_input,
+                    take_captures,
+                )
+                    .chain()
+                    .in_set(VoxelSet::Render),
+            );
+
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            // The composite material captures the texture views when its bind group is prepared,
+            // so the compute views have to be swapped in before that happens.
+            render_app.add_systems(
+                Render,
+                link_compute_texture
+                    .in_set(RenderSet::PrepareAssets)
+                    .after(prepare_assets::<GpuImage>)
+                    .before(prepare_assets::<PreparedMaterial<VoxelCompositeMaterial>>),
+            );
+        }
+    }
+}
+
+/// Fly / walk camera, the editor and the gameplay hotkeys.
+pub struct VoxelInputPlugin;
+
+impl Plugin for VoxelInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraMode>()
+            .init_resource::<CameraSettings>()
+            .init_resource::<EditorState>()
+            .init_resource::<Clipboard>()
+            .init_asset::<Prefab>()
+            .init_asset_loader::<PrefabLoader>()
+            .add_systems(Startup, setup_editor_ui)
+            .add_systems(
+                Update,
+                (
+                    toggle_camera_mode,
+                    camera_movement_system,
+                    character_controller_system.run_if(resource_equals(CameraMode::Walk)),
+                    camera_path_input,
+                    update_camera_path,
+                )
+                    .chain()
+                    .in_set(VoxelSet::Input),
+            )
+            .add_systems(
+                Update,
+                (
+                    undo_redo_system,
+                    toggle_editor,
+                    editor_input,
+                    apply_editor_tool,
+                    update_palette_picker,
+                    draw_brush_preview,
+                    clipboard_input,
+                    draw_clipboard_preview,
+                    cycle_debris_mode,
+                    explode_at_cursor,
+                    pour_at_cursor,
+                    heat_at_cursor,
+                )
+                    .chain()
+                    .in_set(VoxelSet::Edit),
+            );
+    }
+}
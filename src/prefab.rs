@@ -0,0 +1,384 @@
+use crate::editor::{EditorState, EditorTool};
+use crate::history::EditHistory;
+use crate::palette::remap_names;
+use crate::render::VoxelCamera;
+use crate::volume::VoxelVolume;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use std::io;
+
+/// Folder under `assets/` that prefab names refer to.
+pub const PREFAB_DIR: &str = "prefabs";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeMode {
+    /// Every cell of the volume is written, air included.
+    Replace,
+    /// Solid voxels are only written into cells that are air in the world.
+    OnlyAir,
+    /// Only the solid voxels are written, air in the volume leaves the world untouched.
+    OnlySolid,
+}
+
+impl MergeMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Replace => Self::OnlyAir,
+            Self::OnlyAir => Self::OnlySolid,
+            Self::OnlySolid => Self::Replace,
+        }
+    }
+}
+
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct Prefab {
+    pub volume: VoxelVolume,
+    /// Names of the palette the volume was saved with, see `remap_names`.
+    pub palette_names: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct PrefabLoader;
+
+impl AssetLoader for PrefabLoader {
+    type Asset = Prefab;
+    type Settings = ();
+    type Error = io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Prefab, io::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let (volume, palette_names) = VoxelVolume::from_bytes(&bytes)?;
+        Ok(Prefab {
+            volume,
+            palette_names,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vxp"]
+    }
+}
+
+/// Asset path of a prefab. A bare name is a file in `PREFAB_DIR`, anything ending in
+/// `.vxp` is taken as a path relative to `assets/`.
+pub fn prefab_path(name: &str) -> String {
+    if name.ends_with(".vxp") {
+        name.to_string()
+    } else {
+        format!("{}/{}.vxp", PREFAB_DIR, name)
+    }
+}
+
+/// Names of the prefabs saved in `PREFAB_DIR`, sorted.
+pub fn saved_prefabs() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(std::path::Path::new("assets").join(PREFAB_DIR)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "vxp" {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// First `prefab_N` name that is not taken yet.
+fn unused_prefab_name(saved: &[String]) -> String {
+    (1..)
+        .map(|i| format!("prefab_{}", i))
+        .find(|name| !saved.contains(name))
+        .unwrap()
+}
+
+pub fn save_prefab(volume: &VoxelVolume, palette_names: &[String], path: &str) -> io::Result<()> {
+    let path = std::path::Path::new("assets").join(path);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, volume.to_bytes(palette_names))
+}
+
+impl VoxelWorld {
+    /// Copies `min..=max` into a standalone volume.
+    pub fn copy_region(&self, min: IVec3, max: IVec3) -> VoxelVolume {
+        let mut volume = VoxelVolume::new(max - min + IVec3::ONE);
+        self.for_each_solid_in(min, max, |pos, mat_id| volume.set(pos - min, mat_id));
+        volume
+    }
+}
+
+/// Voxel writes that paste `volume` with its minimum corner at `origin`.
+pub fn paste_changes(
+    world: &VoxelWorld,
+    volume: &VoxelVolume,
+    origin: IVec3,
+    mode: MergeMode,
+) -> Vec<(IVec3, u8)> {
+    let mut changes = Vec::new();
+    for y in 0..volume.size.y {
+        for z in 0..volume.size.z {
+            for x in 0..volume.size.x {
+                let local = IVec3::new(x, y, z);
+                let mat_id = volume.get(local);
+                let pos = origin + local;
+                let write = match mode {
+                    MergeMode::Replace => true,
+                    MergeMode::OnlyAir => mat_id != 0 && !world.is_solid(pos),
+                    MergeMode::OnlySolid => mat_id != 0,
+                };
+                if write {
+                    changes.push((pos, mat_id));
+                }
+            }
+        }
+    }
+    changes
+}
+
+#[derive(Resource)]
+pub struct Clipboard {
+    pub volume: Option<VoxelVolume>,
+    /// Corners picked with the select tool, the region is inclusive.
+    pub selection: [Option<IVec3>; 2],
+    pub merge: MergeMode,
+    /// Prefab that Ctrl + S and Ctrl + O use, a name in `PREFAB_DIR` or a path, see
+    /// `prefab_path`.
+    pub prefab: String,
+    next_corner: usize,
+    loading: Option<Handle<Prefab>>,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self {
+            volume: None,
+            selection: [None; 2],
+            merge: MergeMode::Replace,
+            prefab: "clipboard".to_string(),
+            next_corner: 0,
+            loading: None,
+        }
+    }
+}
+
+impl Clipboard {
+    pub fn selected_region(&self) -> Option<(IVec3, IVec3)> {
+        let [Some(a), Some(b)] = self.selection else {
+            return None;
+        };
+        Some((a.min(b), a.max(b)))
+    }
+}
+
+/// Paste origin that centers the clipboard horizontally on the cell in front of the hit.
+fn paste_origin(volume: &VoxelVolume, hit_pos: IVec3, normal: Vec3) -> IVec3 {
+    let target = hit_pos + normal.as_ivec3();
+    target - IVec3::new(volume.size.x / 2, 0, volume.size.z / 2)
+}
+
+/// With the editor open: the select tool picks corners, Ctrl + C / X / V copy, cut and paste,
+/// R rotates the clipboard around y (shift: x), M mirrors it along x (shift: z), N cycles the
+/// merge mode. Ctrl + S saves the clipboard to `Clipboard.prefab` and Ctrl + O loads it back,
+/// Ctrl + Shift + S saves it under a new name and Ctrl + Shift + O loads the next saved prefab.
+pub fn clipboard_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    asset_server: Res<AssetServer>,
+    prefabs: Res<Assets<Prefab>>,
+    editor: Res<EditorState>,
+    mut clipboard: ResMut<Clipboard>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    mut edits: EventWriter<VoxelsEdited>,
+) {
+    if let Some(handle) = clipboard.loading.clone() {
+        if let Some(prefab) = prefabs.get(&handle) {
+            // Keeps materials by name when the palette was reordered since saving.
+            let mut volume = prefab.volume.clone();
+            volume.remap(&remap_names(&prefab.palette_names, &world.palette_names));
+            clipboard.volume = Some(volume);
+            clipboard.loading = None;
+            println!("Loaded prefab {}", prefab_path(&clipboard.prefab));
+        }
+    }
+    if !editor.enabled {
+        return;
+    }
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let hit = camera_q
+        .single()
+        .ok()
+        .and_then(|t| world.raycast(t.translation, *t.forward(), editor.reach));
+
+    if editor.tool == EditorTool::Select && mouse.just_pressed(MouseButton::Left) {
+        if let Some(hit) = hit {
+            let corner = clipboard.next_corner;
+            clipboard.selection[corner] = Some(hit.pos);
+            clipboard.next_corner = 1 - corner;
+        }
+    }
+
+    if !ctrl {
+        if let Some(volume) = clipboard.volume.as_mut() {
+            if keyboard.just_pressed(KeyCode::KeyR) {
+                *volume = volume.rotated(if shift { 0 } else { 1 }, 1);
+            }
+            if keyboard.just_pressed(KeyCode::KeyM) {
+                *volume = volume.mirrored(if shift { 2 } else { 0 });
+            }
+        }
+        if keyboard.just_pressed(KeyCode::KeyN) {
+            clipboard.merge = clipboard.merge.next();
+            println!("Paste merge mode: {:?}", clipboard.merge);
+        }
+        return;
+    }
+
+    let cut = keyboard.just_pressed(KeyCode::KeyX);
+    if keyboard.just_pressed(KeyCode::KeyC) || cut {
+        if let Some((min, max)) = clipboard.selected_region() {
+            let volume = world.copy_region(min, max);
+            println!("Copied {} voxels", volume.iter_solid().count());
+            clipboard.volume = Some(volume);
+            if cut {
+                let mut solids = Vec::new();
+                world.for_each_solid_in(min, max, |pos, _| solids.push(pos));
+                history.begin(&world, "Cut");
+                for pos in solids {
+                    history.set_voxel(&mut world, pos, 0);
+                }
+                history.commit(&world);
+                edits.write(VoxelsEdited { min, max });
+            }
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::KeyV) {
+        if let (Some(volume), Some(hit)) = (clipboard.volume.as_ref(), hit) {
+            let origin = paste_origin(volume, hit.pos, hit.normal);
+            let changes = paste_changes(&world, volume, origin, clipboard.merge);
+            history.begin(&world, "Paste");
+            for (pos, mat_id) in changes {
+                history.set_voxel(&mut world, pos, mat_id);
+            }
+            history.commit(&world);
+            edits.write(VoxelsEdited {
+                min: origin,
+                max: origin + volume.size - IVec3::ONE,
+            });
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::KeyS) && clipboard.volume.is_some() {
+        if shift {
+            clipboard.prefab = unused_prefab_name(&saved_prefabs());
+        }
+        let path = prefab_path(&clipboard.prefab);
+        if let Some(volume) = clipboard.volume.as_ref() {
+            match save_prefab(volume, &world.palette_names, &path) {
+                Ok(()) => println!("Saved prefab {}", path),
+                Err(err) => println!("Failed to save prefab {}: {}", path, err),
+            }
+        }
+    }
+    if keyboard.just_pressed(KeyCode::KeyO) {
+        if shift {
+            let saved = saved_prefabs();
+            let next = saved
+                .iter()
+                .position(|name| *name == clipboard.prefab)
+                .map_or(0, |i| (i + 1) % saved.len().max(1));
+            match saved.get(next) {
+                Some(name) => clipboard.prefab = name.clone(),
+                None => {
+                    println!("No prefabs saved in assets/{}", PREFAB_DIR);
+                    return;
+                }
+            }
+        }
+        clipboard.loading = Some(asset_server.load(prefab_path(&clipboard.prefab)));
+    }
+}
+
+/// Outlines the selection and where the clipboard would be pasted.
+pub fn draw_clipboard_preview(
+    mut gizmos: Gizmos,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    editor: Res<EditorState>,
+    clipboard: Res<Clipboard>,
+    world: Res<VoxelWorld>,
+) {
+    if !editor.enabled {
+        return;
+    }
+    let outline = |gizmos: &mut Gizmos, min: IVec3, size: IVec3, color: Color| {
+        let center = min.as_vec3() + size.as_vec3() * 0.5;
+        gizmos.cuboid(
+            Transform::from_translation(center).with_scale(size.as_vec3()),
+            color,
+        );
+    };
+    if let Some((min, max)) = clipboard.selected_region() {
+        outline(
+            &mut gizmos,
+            min,
+            max - min + IVec3::ONE,
+            Color::srgb(1.0, 0.9, 0.2),
+        );
+    }
+    if editor.tool != EditorTool::Select {
+        return;
+    }
+    let (Some(volume), Ok(transform)) = (clipboard.volume.as_ref(), camera_q.single()) else {
+        return;
+    };
+    if let Some(hit) = world.raycast(transform.translation, *transform.forward(), editor.reach) {
+        let origin = paste_origin(volume, hit.pos, hit.normal);
+        outline(&mut gizmos, origin, volume.size, Color::srgb(0.2, 0.8, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefab_names_and_paths() {
+        assert_eq!(prefab_path("bridge"), "prefabs/bridge.vxp");
+        assert_eq!(prefab_path("ships/sloop.vxp"), "ships/sloop.vxp");
+        let saved = vec![
+            "clipboard".to_string(),
+            "prefab_1".to_string(),
+            "prefab_3".to_string(),
+        ];
+        assert_eq!(unused_prefab_name(&saved), "prefab_2");
+        assert_eq!(unused_prefab_name(&[]), "prefab_1");
+    }
+
+    #[test]
+    fn copy_region_keeps_only_solid_voxels() {
+        let mut world = VoxelWorld::default();
+        world.set_voxel(IVec3::new(1, 1, 1), 2);
+        world.set_voxel(IVec3::new(3, 2, 1), 4);
+        world.set_voxel(IVec3::new(9, 9, 9), 4);
+        let volume = world.copy_region(IVec3::ZERO, IVec3::new(3, 3, 3));
+        assert_eq!(volume.size, IVec3::splat(4));
+        let solids: Vec<(IVec3, u8)> = volume.iter_solid().collect();
+        assert_eq!(solids, [(IVec3::new(1, 1, 1), 2), (IVec3::new(3, 2, 1), 4)]);
+    }
+}
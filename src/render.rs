@@ -1,12 +1,19 @@
 use crate::compute::WriteTextureWorker;
-use crate::config::AppSettings;
+use crate::config::{
+    AppSettings, AtmosphereSettings, LevelOfDetail, RenderDebugMode, TemporalAntiAliasing,
+};
+use bevy::core_pipeline::tonemapping::Tonemapping;
+use bevy::image::ImageSampler;
+use bevy::pbr::NotShadowCaster;
 use bevy::prelude::*;
 use bevy::render::extract_resource::ExtractResource;
 use bevy::render::render_asset::{RenderAssetUsages, RenderAssets};
 use bevy::render::render_resource::{
-    Extent3d, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    AsBindGroup, Extent3d, ShaderRef, Texture, TextureDimension, TextureFormat, TextureUsages,
+    TextureView,
 };
 use bevy::render::texture::GpuImage;
+use bevy::render::view::NoFrustumCulling;
 use bevy::window::WindowResized;
 use bevy_app_compute::prelude::*;
 use iyes_perf_ui::entries::PerfUiAllEntries;
@@ -14,26 +21,71 @@ use iyes_perf_ui::entries::PerfUiAllEntries;
 #[derive(Resource, Clone, ExtractResource, Default)]
 pub struct DisplayImage(pub Handle<Image>);
 
+#[derive(Resource, Clone, ExtractResource, Default)]
+pub struct DepthImage(pub Handle<Image>);
+
 #[derive(Resource, Clone, ExtractResource)]
-pub struct ComputeTransfer(pub TextureView);
+pub struct ComputeTransfer {
+    pub color: TextureView,
+    /// Swapped in as well so GPU readbacks of the display image copy the compute output.
+    pub color_texture: Texture,
+    pub depth: TextureView,
+}
+
+/// Size of the compute textures currently in use.
+#[derive(Resource, Clone, Copy, Default)]
+pub struct RenderResolution(pub UVec2);
 
 #[derive(Component)]
 pub struct VoxelCamera;
 
+/// Fullscreen pass that draws the raytraced voxels into the `Camera3d` opaque phase,
+/// writing their depth so regular meshes are depth-tested against them.
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+pub struct VoxelCompositeMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub color: Handle<Image>,
+    #[texture(2, sample_type = "float", filterable = false)]
+    pub depth: Handle<Image>,
+    /// x: `UpscaleFilter`, y: sharpness.
+    #[uniform(3)]
+    pub upscale: Vec4,
+}
+
+impl VoxelCompositeMaterial {
+    fn upscale_params(settings: &AppSettings) -> Vec4 {
+        Vec4::new(
+            settings.upscale_filter as u32 as f32,
+            settings.sharpness,
+            0.0,
+            0.0,
+        )
+    }
+}
+
+impl Material for VoxelCompositeMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/voxel_composite.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/voxel_composite.wgsl".into()
+    }
+}
+
 pub fn setup(mut commands: Commands) {
-    commands.spawn((
-        Camera2d::default(),
-        Camera {
-            order: 1,
-            ..default()
-        },
-    ));
     commands.spawn((
         Camera3d::default(),
         VoxelCamera,
+        Msaa::Off,
+        Tonemapping::None,
         Transform::from_xyz(0.0, 0.0, 0.0).looking_at(Vec3::splat(512.0), Vec3::Y),
     ));
 
+    // Lights the rasterized voxel bodies and debris, `sync_sun_light` points it at the sun.
+    commands.spawn(DirectionalLight::default());
+
     commands.spawn(PerfUiAllEntries::default());
 }
 
@@ -45,47 +97,74 @@ pub fn handle_resize(world: &mut World) {
         .cloned()
         .collect();
 
-    if events.is_empty() && world.get_resource::<DisplayImage>().is_some() {
+    if let Some(last_event) = events.last() {
+        let mut settings = world.resource_mut::<AppSettings>();
+        settings.width = last_event.width as u32;
+        settings.height = last_event.height as u32;
+    }
+
+    let settings = world.resource::<AppSettings>();
+    let (width, height) = (settings.render_width(), settings.render_height());
+    let upscale = VoxelCompositeMaterial::upscale_params(settings);
+
+    let current = world.get_resource::<RenderResolution>().map(|r| r.0);
+    if current == Some(UVec2::new(width, height)) && world.get_resource::<DisplayImage>().is_some()
+    {
         return;
     }
 
-    let (width, height) = {
-        let mut settings = world.resource_mut::<AppSettings>();
-        if let Some(last_event) = events.last() {
-            settings.width = last_event.width as u32;
-            settings.height = last_event.height as u32;
-        }
-        (settings.width, settings.height)
-    };
+    let mut color_image = create_gpu_image(width, height, TextureFormat::Rgba8Unorm);
+    color_image.sampler = ImageSampler::linear();
 
-    let new_image = create_gpu_image(width, height);
     let mut images = world.resource_mut::<Assets<Image>>();
-    let handle = images.add(new_image);
+    let handle = images.add(color_image);
+    let depth_handle = images.add(create_gpu_image(width, height, TextureFormat::R32Float));
+
+    let material = VoxelCompositeMaterial {
+        color: handle.clone(),
+        depth: depth_handle.clone(),
+        upscale,
+    };
 
-    let mut sprite_query = world.query::<(Entity, &mut Sprite)>();
-    let sprite_entity = sprite_query.iter_mut(world).next();
+    let mut composite_query = world.query::<&MeshMaterial3d<VoxelCompositeMaterial>>();
+    let composite_material = composite_query.iter(world).next().map(|m| m.0.clone());
 
-    if let Some((_, mut sprite)) = sprite_entity {
-        sprite.image = handle.clone();
-        sprite.custom_size = Some(Vec2::new(width as f32, height as f32));
+    if let Some(material_handle) = composite_material {
+        let mut materials = world.resource_mut::<Assets<VoxelCompositeMaterial>>();
+        if let Some(existing) = materials.get_mut(&material_handle) {
+            *existing = material;
+        }
     } else {
+        let mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Rectangle::new(2.0, 2.0));
+        let material_handle = world
+            .resource_mut::<Assets<VoxelCompositeMaterial>>()
+            .add(material);
         world.spawn((
-            Sprite {
-                image: handle.clone(),
-                custom_size: Some(Vec2::new(width as f32, height as f32)),
-                ..default()
-            },
-            Transform::from_scale(Vec3::splat(1.0)),
+            Mesh3d(mesh),
+            MeshMaterial3d(material_handle),
+            NoFrustumCulling,
+            NotShadowCaster,
         ));
     }
 
     world.insert_resource(DisplayImage(handle));
-    println!("Resizing window and recreating worker");
+    world.insert_resource(DepthImage(depth_handle));
+    world.insert_resource(RenderResolution(UVec2::new(width, height)));
+    println!(
+        "Resizing render target to {}x{} and recreating worker",
+        width, height
+    );
     let new_worker = WriteTextureWorker::build(world);
     world.insert_resource(new_worker);
 }
 
-pub fn create_gpu_image(width: u32, height: u32) -> Image {
+pub fn create_gpu_image(width: u32, height: u32, format: TextureFormat) -> Image {
+    let pixel: &[u8] = match format {
+        TextureFormat::Rgba8Unorm => &[0, 0, 0, 255],
+        _ => &[0; 4],
+    };
     let mut image = Image::new_fill(
         Extent3d {
             width,
@@ -93,8 +172,8 @@ pub fn create_gpu_image(width: u32, height: u32) -> Image {
             ..default()
         },
         TextureDimension::D2,
-        &[0, 0, 0, 255],
-        TextureFormat::Rgba8Unorm,
+        pixel,
+        format,
         RenderAssetUsages::RENDER_WORLD,
     );
 
@@ -108,17 +187,112 @@ pub fn extract_compute_view(
     worker: Res<AppComputeWorker<WriteTextureWorker>>,
     mut commands: Commands,
 ) {
-    if let Some(texture) = worker.get_texture("out_tex") {
-        commands.insert_resource(ComputeTransfer(texture.view().clone()));
+    if let (Some(color), Some(depth)) = (
+        worker.get_texture("out_tex"),
+        worker.get_texture("depth_tex"),
+    ) {
+        commands.insert_resource(ComputeTransfer {
+            color: color.view().clone(),
+            color_texture: color.texture().clone(),
+            depth: depth.view().clone(),
+        });
     }
 }
 
 pub fn link_compute_texture(
     display_image: Res<DisplayImage>,
+    depth_image: Res<DepthImage>,
     compute_transfer: Res<ComputeTransfer>,
     mut gpu_images: ResMut<RenderAssets<GpuImage>>,
 ) {
     if let Some(gpu_image) = gpu_images.get_mut(&display_image.0) {
-        gpu_image.texture_view = compute_transfer.0.clone();
+        gpu_image.texture = compute_transfer.color_texture.clone();
+        gpu_image.texture_view = compute_transfer.color.clone();
+    }
+    if let Some(gpu_image) = gpu_images.get_mut(&depth_image.0) {
+        gpu_image.texture_view = compute_transfer.depth.clone();
+    }
+}
+
+pub fn cycle_render_debug_mode(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut debug_mode: ResMut<RenderDebugMode>,
+) {
+    if keyboard.just_pressed(KeyCode::F1) {
+        *debug_mode = debug_mode.next();
+        println!("Render debug mode: {:?}", *debug_mode);
+    }
+}
+
+pub fn adjust_render_scale(keyboard: Res<ButtonInput<KeyCode>>, mut settings: ResMut<AppSettings>) {
+    let mut render_scale = settings.render_scale;
+    if keyboard.just_pressed(KeyCode::BracketLeft) {
+        render_scale -= 0.25;
+    }
+    if keyboard.just_pressed(KeyCode::BracketRight) {
+        render_scale += 0.25;
+    }
+    render_scale = render_scale.clamp(AppSettings::MIN_RENDER_SCALE, AppSettings::MAX_RENDER_SCALE);
+
+    if render_scale != settings.render_scale {
+        settings.render_scale = render_scale;
+        println!("Render scale: {}", render_scale);
+    }
+    if keyboard.just_pressed(KeyCode::F2) {
+        settings.upscale_filter = settings.upscale_filter.next();
+        println!("Upscale filter: {:?}", settings.upscale_filter);
+    }
+}
+
+pub fn update_composite_params(
+    settings: Res<AppSettings>,
+    composite_q: Query<&MeshMaterial3d<VoxelCompositeMaterial>>,
+    mut materials: ResMut<Assets<VoxelCompositeMaterial>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    let upscale = VoxelCompositeMaterial::upscale_params(&settings);
+    for handle in &composite_q {
+        let needs_update = materials
+            .get(&handle.0)
+            .is_some_and(|m| m.upscale != upscale);
+        if needs_update {
+            if let Some(material) = materials.get_mut(&handle.0) {
+                material.upscale = upscale;
+            }
+        }
+    }
+}
+
+pub fn toggle_taa(keyboard: Res<ButtonInput<KeyCode>>, mut taa: ResMut<TemporalAntiAliasing>) {
+    if keyboard.just_pressed(KeyCode::F4) {
+        taa.enabled = !taa.enabled;
+        println!("TAA: {}", if taa.enabled { "on" } else { "off" });
+    }
+}
+
+pub fn toggle_lod(keyboard: Res<ButtonInput<KeyCode>>, mut lod: ResMut<LevelOfDetail>) {
+    if keyboard.just_pressed(KeyCode::F8) {
+        lod.enabled = !lod.enabled;
+        println!("LOD: {}", if lod.enabled { "on" } else { "off" });
+    }
+}
+
+pub fn sync_sun_light(
+    atmosphere: Res<AtmosphereSettings>,
+    mut light_q: Query<(&mut Transform, &mut DirectionalLight)>,
+) {
+    if !atmosphere.is_changed() {
+        return;
+    }
+    let sun_direction = atmosphere.sun_direction.normalize_or(Vec3::Y);
+    for (mut transform, mut light) in &mut light_q {
+        transform.look_to(-sun_direction, Vec3::Y);
+        light.color = Color::srgb(
+            atmosphere.sun_color.x,
+            atmosphere.sun_color.y,
+            atmosphere.sun_color.z,
+        );
     }
 }
@@ -0,0 +1,401 @@
+use crate::structure::{CollapseMode, IslandDetached, NEIGHBOURS, StructuralSettings};
+use crate::volume::VoxelVolume;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited};
+use bevy::math::Mat3;
+use bevy::prelude::*;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct RigidBodySettings {
+    pub substeps: u32,
+    pub restitution: f32,
+    /// Bodies slower than this (voxels per second) count as resting.
+    pub rest_linear_speed: f32,
+    pub rest_angular_speed: f32,
+    /// How long a body must rest before it is baked back into the world.
+    pub rest_time: f32,
+    /// Bodies falling this far below the ground level are discarded.
+    pub kill_depth: i32,
+}
+
+impl Default for RigidBodySettings {
+    fn default() -> Self {
+        Self {
+            substeps: 4,
+            restitution: 0.2,
+            rest_linear_speed: 0.5,
+            rest_angular_speed: 0.2,
+            rest_time: 0.5,
+            kill_depth: 64,
+        }
+    }
+}
+
+/// A detached chunk of voxels simulated as a rigid body. Distances are in voxels, the
+/// entity's `Transform` sits at the center of mass.
+#[derive(Component)]
+pub struct VoxelBody {
+    pub volume: VoxelVolume,
+    /// Center of mass in volume coordinates.
+    pub center_of_mass: Vec3,
+    pub mass: f32,
+    pub inv_inertia: Mat3,
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+    pub rest_timer: f32,
+    /// Collision probes relative to the center of mass, with the material they belong to.
+    probes: Vec<(Vec3, u8)>,
+}
+
+impl VoxelBody {
+    /// Builds a body from a volume, mass and inertia come from `Material.density`.
+    pub fn new(volume: VoxelVolume, world: &VoxelWorld, voxel_size: f32) -> Option<Self> {
+        let voxel_volume = voxel_size * voxel_size * voxel_size;
+        let solids: Vec<(IVec3, u8)> = volume.iter_solid().collect();
+
+        let mut mass = 0.0;
+        let mut weighted = Vec3::ZERO;
+        for &(pos, mat_id) in &solids {
+            let m = world.material(mat_id).density * voxel_volume;
+            mass += m;
+            weighted += (pos.as_vec3() + 0.5) * m;
+        }
+        if mass <= 0.0 {
+            return None;
+        }
+        let center_of_mass = weighted / mass;
+
+        // Each voxel is a unit cube: m/6 about its own center plus the parallel axis term.
+        let mut inertia = Mat3::ZERO;
+        for &(pos, mat_id) in &solids {
+            let m = world.material(mat_id).density * voxel_volume;
+            let r = pos.as_vec3() + 0.5 - center_of_mass;
+            inertia += Mat3::from_diagonal(Vec3::splat(r.dot(r) + 1.0 / 6.0)) * m;
+            inertia -= Mat3::from_cols(r * r.x, r * r.y, r * r.z) * m;
+        }
+
+        let mut probes = Vec::new();
+        for &(pos, mat_id) in &solids {
+            if !volume.is_surface(pos) {
+                continue;
+            }
+            for corner in 0..8 {
+                let offset = Vec3::new(
+                    (corner & 1) as f32,
+                    ((corner >> 1) & 1) as f32,
+                    ((corner >> 2) & 1) as f32,
+                ) * 0.9
+                    + 0.05;
+                probes.push((pos.as_vec3() + offset - center_of_mass, mat_id));
+            }
+        }
+
+        Some(Self {
+            volume,
+            center_of_mass,
+            mass,
+            inv_inertia: inertia.inverse(),
+            linear_velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            rest_timer: 0.0,
+            probes,
+        })
+    }
+
+    fn world_inv_inertia(&self, rotation: Quat) -> Mat3 {
+        let r = Mat3::from_quat(rotation);
+        r * self.inv_inertia * r.transpose()
+    }
+
+    fn apply_impulse(&mut self, impulse: Vec3, r: Vec3, inv_inertia: Mat3) {
+        self.linear_velocity += impulse / self.mass;
+        self.angular_velocity += inv_inertia * r.cross(impulse);
+    }
+}
+
+/// Direction out of a solid world cell, towards its emptiest side.
+fn contact_normal(world: &VoxelWorld, cell: IVec3) -> Vec3 {
+    let mut normal = Vec3::ZERO;
+    for offset in NEIGHBOURS {
+        if !world.is_solid(cell + offset) {
+            normal += offset.as_vec3();
+        }
+    }
+    // Buried cells or symmetric openings push up, which resolves most resting contacts.
+    let normal = if normal == Vec3::ZERO {
+        Vec3::Y
+    } else {
+        normal
+    };
+    let abs = normal.abs();
+    if abs.y >= abs.x && abs.y >= abs.z {
+        Vec3::Y * normal.y.signum()
+    } else if abs.x >= abs.z {
+        Vec3::X * normal.x.signum()
+    } else {
+        Vec3::Z * normal.z.signum()
+    }
+}
+
+fn step_body(
+    body: &mut VoxelBody,
+    transform: &mut Transform,
+    world: &VoxelWorld,
+    ground_level: i32,
+    gravity: Vec3,
+    restitution: f32,
+    dt: f32,
+) {
+    body.linear_velocity += gravity * dt;
+    transform.translation += body.linear_velocity * dt;
+    let spin = Quat::from_xyzw(
+        body.angular_velocity.x,
+        body.angular_velocity.y,
+        body.angular_velocity.z,
+        0.0,
+    ) * transform.rotation;
+    transform.rotation = (transform.rotation + spin * (0.5 * dt)).normalize();
+
+    let inv_inertia = body.world_inv_inertia(transform.rotation);
+    let mut correction = Vec3::ZERO;
+
+    for i in 0..body.probes.len() {
+        let (local, mat_id) = body.probes[i];
+        let r = transform.rotation * local;
+        let point = transform.translation + r;
+        let cell = point.floor().as_ivec3();
+        // Everything below the ground level is treated as solid ground.
+        let (world_mat, normal) = if cell.y < ground_level {
+            (0, Vec3::Y)
+        } else {
+            let world_mat = world.get_voxel(cell);
+            if world_mat == 0 {
+                continue;
+            }
+            (world_mat, contact_normal(world, cell))
+        };
+
+        let exit = if cell.y < ground_level {
+            ground_level as f32 - point.y
+        } else {
+            (cell.as_vec3() + 0.5 + normal * 0.5 - point).dot(normal)
+        };
+        let push = normal * exit.max(0.0);
+        correction = Vec3::select(push.abs().cmpgt(correction.abs()), push, correction);
+
+        let velocity = body.linear_velocity + body.angular_velocity.cross(r);
+        let vn = velocity.dot(normal);
+        if vn >= 0.0 {
+            continue;
+        }
+
+        let rn = r.cross(normal);
+        let k = 1.0 / body.mass + normal.dot((inv_inertia * rn).cross(r));
+        let jn = -(1.0 + restitution) * vn / k;
+        body.apply_impulse(normal * jn, r, inv_inertia);
+
+        let velocity = body.linear_velocity + body.angular_velocity.cross(r);
+        let tangent_velocity = velocity - normal * velocity.dot(normal);
+        let speed = tangent_velocity.length();
+        if speed > 1e-5 {
+            let tangent = tangent_velocity / speed;
+            let rt = r.cross(tangent);
+            let kt = 1.0 / body.mass + tangent.dot((inv_inertia * rt).cross(r));
+            let friction =
+                (world.material(mat_id).friction * world.material(world_mat).friction).sqrt();
+            let jt = (speed / kt).min(friction * jn);
+            body.apply_impulse(-tangent * jt, r, inv_inertia);
+        }
+    }
+
+    // Push out along the deepest penetration per axis instead of summing every probe.
+    transform.translation += correction;
+}
+
+/// Writes the body's voxels back into the world at their nearest grid cells.
+fn bake_body(
+    body: &VoxelBody,
+    transform: &Transform,
+    world: &mut VoxelWorld,
+    ground_level: i32,
+) -> (IVec3, IVec3) {
+    let mut min = IVec3::MAX;
+    let mut max = IVec3::MIN;
+    for (pos, mat_id) in body.volume.iter_solid() {
+        let local = pos.as_vec3() + 0.5 - body.center_of_mass;
+        let cell = (transform.translation + transform.rotation * local)
+            .floor()
+            .as_ivec3();
+        if cell.y >= ground_level && !world.is_solid(cell) {
+            world.set_voxel(cell, mat_id);
+            min = min.min(cell);
+            max = max.max(cell);
+        }
+    }
+    (min, max)
+}
+
+/// Body placed so that its volume starts at world cell `origin`.
+pub fn body_bundle(body: VoxelBody, origin: IVec3) -> impl Bundle {
+    let translation = origin.as_vec3() + body.center_of_mass;
+    (Transform::from_translation(translation), body)
+}
+
+pub fn spawn_rigid_bodies(
+    mut commands: Commands,
+    mut detached: EventReader<IslandDetached>,
+    world: Res<VoxelWorld>,
+    structural: Res<StructuralSettings>,
+) {
+    // The other modes are handled by `collapse_islands`, which already put the voxels back
+    // or dropped them.
+    if structural.collapse != CollapseMode::RigidBody {
+        detached.clear();
+        return;
+    }
+    for IslandDetached(island) in detached.read() {
+        let mut volume = VoxelVolume::new(island.max - island.min + IVec3::ONE);
+        for &(pos, mat_id) in &island.voxels {
+            volume.set(pos - island.min, mat_id);
+        }
+        let Some(body) = VoxelBody::new(volume, &world, structural.voxel_size) else {
+            continue;
+        };
+        commands.spawn(body_bundle(body, island.min));
+    }
+}
+
+/// Gives new bodies a mesh of their voxels, bodies simulate without one when headless.
+pub fn attach_body_meshes(
+    mut commands: Commands,
+    bodies: Query<(Entity, &VoxelBody), Without<Mesh3d>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    world: Res<VoxelWorld>,
+) {
+    for (entity, body) in &bodies {
+        let mesh = body.volume.build_mesh(&world.palette, -body.center_of_mass);
+        commands.entity(entity).insert((
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(materials.add(StandardMaterial::default())),
+        ));
+    }
+}
+
+pub fn step_rigid_bodies(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut bodies: Query<(Entity, &mut VoxelBody, &mut Transform)>,
+    mut world: ResMut<VoxelWorld>,
+    mut edits: EventWriter<VoxelsEdited>,
+    settings: Res<RigidBodySettings>,
+    structural: Res<StructuralSettings>,
+) {
+    let dt = time.delta_secs().min(1.0 / 30.0);
+    if dt <= 0.0 {
+        return;
+    }
+    let sub_dt = dt / settings.substeps as f32;
+    let gravity = Vec3::NEG_Y * structural.gravity / structural.voxel_size;
+
+    for (entity, mut body, mut transform) in &mut bodies {
+        for _ in 0..settings.substeps {
+            step_body(
+                &mut body,
+                &mut transform,
+                &world,
+                structural.ground_level,
+                gravity,
+                settings.restitution,
+                sub_dt,
+            );
+        }
+
+        if transform.translation.y < (structural.ground_level - settings.kill_depth) as f32 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let resting = body.linear_velocity.length() < settings.rest_linear_speed
+            && body.angular_velocity.length() < settings.rest_angular_speed;
+        body.rest_timer = if resting { body.rest_timer + dt } else { 0.0 };
+
+        if body.rest_timer >= settings.rest_time {
+            let (min, max) = bake_body(&body, &transform, &mut world, structural.ground_level);
+            if min.cmple(max).all() {
+                edits.write(VoxelsEdited { min, max });
+            }
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::EditHistory;
+    use crate::structure::{collapse_islands, solve_structures};
+
+    fn count_voxels(world: &VoxelWorld) -> usize {
+        world
+            .sectors
+            .values()
+            .flat_map(|sector| sector.bricks.values())
+            .map(|brick| brick.voxels.iter().filter(|&&v| v != 0).count())
+            .sum()
+    }
+
+    /// Detaches a floating 2x2x2 cube under `mode`, returns the voxels left in the world and
+    /// the number of bodies spawned.
+    fn collapse(mode: CollapseMode) -> (usize, usize) {
+        let mut app = App::new();
+        let mut world = VoxelWorld::default();
+        for y in 10..12 {
+            for z in 0..2 {
+                for x in 0..2 {
+                    world.set_voxel(IVec3::new(x, y, z), 1);
+                }
+            }
+        }
+        app.insert_resource(world)
+            .insert_resource(StructuralSettings {
+                collapse: mode,
+                ..default()
+            })
+            .init_resource::<EditHistory>()
+            .add_event::<VoxelsEdited>()
+            .add_event::<IslandDetached>()
+            .add_systems(
+                Update,
+                (solve_structures, collapse_islands, spawn_rigid_bodies).chain(),
+            );
+        app.world_mut().send_event(VoxelsEdited {
+            min: IVec3::new(0, 10, 0),
+            max: IVec3::new(1, 11, 1),
+        });
+        app.update();
+        app.update();
+
+        let voxels = count_voxels(app.world().resource::<VoxelWorld>());
+        let bodies = app
+            .world_mut()
+            .query::<&VoxelBody>()
+            .iter(app.world())
+            .count();
+        (voxels, bodies)
+    }
+
+    #[test]
+    fn remove_deletes_the_island() {
+        assert_eq!(collapse(CollapseMode::Remove), (0, 0));
+    }
+
+    #[test]
+    fn drop_moves_the_island_without_a_body() {
+        assert_eq!(collapse(CollapseMode::Drop), (8, 0));
+    }
+
+    #[test]
+    fn rigid_body_turns_the_island_into_one_body() {
+        assert_eq!(collapse(CollapseMode::RigidBody), (0, 1));
+    }
+}
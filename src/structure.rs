@@ -0,0 +1,468 @@
+use crate::config::PHASE_SOLID;
+use crate::history::EditHistory;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited};
+use bevy::math::IVec3;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+
+pub const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollapseMode {
+    /// Unsupported voxels are deleted.
+    Remove,
+    /// Unsupported islands fall straight down until they land on something.
+    Drop,
+    /// Unsupported islands become dynamic `VoxelBody` entities.
+    RigidBody,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct StructuralSettings {
+    /// Voxels at or below this height are anchored to the ground.
+    pub ground_level: i32,
+    /// Edge length of a voxel in meters.
+    pub voxel_size: f32,
+    pub gravity: f32,
+    /// Connected structures larger than this are assumed to be supported, to bound the search.
+    pub max_search_voxels: usize,
+    pub collapse: CollapseMode,
+}
+
+impl Default for StructuralSettings {
+    fn default() -> Self {
+        Self {
+            ground_level: 0,
+            voxel_size: 0.1,
+            gravity: 9.81,
+            max_search_voxels: 262_144,
+            collapse: CollapseMode::RigidBody,
+        }
+    }
+}
+
+/// A connected group of voxels that lost its support, positions are sorted.
+#[derive(Clone, Debug)]
+pub struct VoxelIsland {
+    pub voxels: Vec<(IVec3, u8)>,
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl VoxelIsland {
+    fn from_positions(world: &VoxelWorld, mut positions: Vec<IVec3>) -> Self {
+        positions.sort_by_key(|p| (p.y, p.z, p.x));
+        let min = positions.iter().copied().fold(IVec3::MAX, IVec3::min);
+        let max = positions.iter().copied().fold(IVec3::MIN, IVec3::max);
+        let voxels = positions
+            .into_iter()
+            .map(|p| (p, world.get_voxel(p)))
+            .collect();
+        Self { voxels, min, max }
+    }
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct IslandDetached(pub VoxelIsland);
+
+#[derive(Clone, Copy, PartialEq)]
+struct Support {
+    budget: f32,
+    pos: IVec3,
+}
+
+impl Eq for Support {}
+
+impl Ord for Support {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.budget
+            .total_cmp(&other.budget)
+            .then_with(|| other.pos.to_array().cmp(&self.pos.to_array()))
+    }
+}
+
+impl PartialOrd for Support {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Granular and liquid voxels are left to the cellular simulation and never carry load.
+fn is_structural(world: &VoxelWorld, pos: IVec3) -> bool {
+    let mat_id = world.get_voxel(pos);
+    mat_id != 0 && world.material(mat_id).phase == PHASE_SOLID
+}
+
+/// Collects the solid voxels connected to `seed`. Gives up with the partial set once the
+/// limit is exceeded or a component already known to be too large is reached.
+fn flood_component(
+    world: &VoxelWorld,
+    seed: IVec3,
+    limit: usize,
+    too_large: &HashSet<IVec3>,
+) -> Result<Vec<IVec3>, Vec<IVec3>> {
+    let mut visited: HashSet<IVec3> = HashSet::from_iter([seed]);
+    let mut component = vec![seed];
+    let mut queue = VecDeque::from([seed]);
+
+    while let Some(pos) = queue.pop_front() {
+        for offset in NEIGHBOURS {
+            let next = pos + offset;
+            if !is_structural(world, next) || !visited.insert(next) {
+                continue;
+            }
+            component.push(next);
+            if component.len() > limit || too_large.contains(&next) {
+                return Err(component);
+            }
+            queue.push_back(next);
+        }
+    }
+    Ok(component)
+}
+
+/// Finds the voxels of `component` that cannot be held up.
+///
+/// Load is estimated with a support budget: anchored voxels start with their material's
+/// `yield_strength` (kPa), stacking straight up keeps the budget, and every horizontal or
+/// hanging step spends the weight of the cantilevered voxel (`density * g * voxel_size`).
+/// A voxel is supported if some path from the ground reaches it with budget left over.
+fn unsupported_voxels(
+    world: &VoxelWorld,
+    component: &[IVec3],
+    settings: &StructuralSettings,
+) -> Vec<IVec3> {
+    let members: HashSet<IVec3> = component.iter().copied().collect();
+    let mut best: HashMap<IVec3, f32> = HashMap::default();
+    let mut heap = BinaryHeap::new();
+
+    for &pos in component {
+        if pos.y <= settings.ground_level {
+            let budget = world.material(world.get_voxel(pos)).yield_strength;
+            best.insert(pos, budget);
+            heap.push(Support { budget, pos });
+        }
+    }
+
+    while let Some(Support { budget, pos }) = heap.pop() {
+        if best.get(&pos).is_some_and(|&b| b > budget) {
+            continue;
+        }
+        for offset in NEIGHBOURS {
+            let next = pos + offset;
+            if !members.contains(&next) {
+                continue;
+            }
+            let material = world.material(world.get_voxel(next));
+            let mut next_budget = budget.min(material.yield_strength);
+            if offset != IVec3::Y {
+                next_budget -= material.density * settings.gravity * settings.voxel_size / 1000.0;
+            }
+            if next_budget < 0.0 || best.get(&next).is_some_and(|&b| b >= next_budget) {
+                continue;
+            }
+            best.insert(next, next_budget);
+            heap.push(Support {
+                budget: next_budget,
+                pos: next,
+            });
+        }
+    }
+
+    component
+        .iter()
+        .copied()
+        .filter(|pos| !best.contains_key(pos))
+        .collect()
+}
+
+/// Splits a set of voxels into 6-connected islands, in a deterministic order.
+fn split_islands(world: &VoxelWorld, mut voxels: Vec<IVec3>) -> Vec<VoxelIsland> {
+    voxels.sort_by_key(|p| (p.y, p.z, p.x));
+    let remaining: HashSet<IVec3> = voxels.iter().copied().collect();
+    let mut visited: HashSet<IVec3> = HashSet::default();
+    let mut islands = Vec::new();
+
+    for &seed in &voxels {
+        if !visited.insert(seed) {
+            continue;
+        }
+        let mut island = vec![seed];
+        let mut queue = VecDeque::from([seed]);
+        while let Some(pos) = queue.pop_front() {
+            for offset in NEIGHBOURS {
+                let next = pos + offset;
+                if remaining.contains(&next) && visited.insert(next) {
+                    island.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        islands.push(VoxelIsland::from_positions(world, island));
+    }
+    islands
+}
+
+/// Finds every island around the edited region that is no longer held up by the ground.
+pub fn find_unsupported_islands(
+    world: &VoxelWorld,
+    min: IVec3,
+    max: IVec3,
+    settings: &StructuralSettings,
+) -> Vec<VoxelIsland> {
+    let mut checked: HashSet<IVec3> = HashSet::default();
+    let mut too_large: HashSet<IVec3> = HashSet::default();
+    let mut islands = Vec::new();
+
+    let (min, max) = (min - IVec3::ONE, max + IVec3::ONE);
+    for y in min.y..=max.y {
+        for z in min.z..=max.z {
+            for x in min.x..=max.x {
+                let seed = IVec3::new(x, y, z);
+                if checked.contains(&seed)
+                    || too_large.contains(&seed)
+                    || !is_structural(world, seed)
+                {
+                    continue;
+                }
+                match flood_component(world, seed, settings.max_search_voxels, &too_large) {
+                    Ok(component) => {
+                        let unsupported = unsupported_voxels(world, &component, settings);
+                        checked.extend(component);
+                        if !unsupported.is_empty() {
+                            islands.extend(split_islands(world, unsupported));
+                        }
+                    }
+                    Err(partial) => too_large.extend(partial),
+                }
+            }
+        }
+    }
+    islands
+}
+
+/// How far an island can fall before one of its voxels would land on the world.
+pub fn drop_distance(world: &VoxelWorld, island: &VoxelIsland, ground_level: i32) -> i32 {
+    let members: HashSet<IVec3> = island.voxels.iter().map(|&(p, _)| p).collect();
+    let mut distance = i32::MAX;
+
+    for &(pos, _) in &island.voxels {
+        if members.contains(&(pos + IVec3::NEG_Y)) {
+            continue;
+        }
+        let mut fall = 0;
+        let mut below = pos + IVec3::NEG_Y;
+        while below.y > ground_level - 1 && fall < distance && !world.is_solid(below) {
+            fall += 1;
+            below.y -= 1;
+        }
+        distance = distance.min(fall);
+    }
+    if distance == i32::MAX { 0 } else { distance }
+}
+
+pub fn solve_structures(
+    mut edits: EventReader<VoxelsEdited>,
+    mut detached: EventWriter<IslandDetached>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    settings: Res<StructuralSettings>,
+) {
+    for edit in edits.read() {
+        let islands = find_unsupported_islands(&world, edit.min, edit.max, &settings);
+        for island in islands {
+            for &(pos, _) in &island.voxels {
+                world.set_voxel(pos, 0);
+            }
+            history.amend(&world, &island.voxels);
+            println!(
+                "Structure collapsed: {} voxels detached",
+                island.voxels.len()
+            );
+            detached.write(IslandDetached(island));
+        }
+    }
+}
+
+pub fn collapse_islands(
+    mut detached: EventReader<IslandDetached>,
+    mut edits: EventWriter<VoxelsEdited>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    settings: Res<StructuralSettings>,
+) {
+    for IslandDetached(island) in detached.read() {
+        match settings.collapse {
+            CollapseMode::Remove | CollapseMode::RigidBody => {
+                edits.write(VoxelsEdited {
+                    min: island.min,
+                    max: island.max,
+                });
+            }
+            CollapseMode::Drop => {
+                let fall = drop_distance(&world, island, settings.ground_level);
+                let landed: Vec<(IVec3, u8)> = island
+                    .voxels
+                    .iter()
+                    .map(|&(pos, mat_id)| {
+                        let target = pos + IVec3::NEG_Y * fall;
+                        (target, world.set_voxel(target, mat_id))
+                    })
+                    .collect();
+                history.amend(&world, &landed);
+                edits.write(VoxelsEdited {
+                    min: island.min + IVec3::NEG_Y * fall,
+                    max: island.max,
+                });
+            }
+        }
+    }
+    // Collapses found later are caused by the simulations, not by the last edit.
+    history.seal();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Material;
+
+    /// A world whose material 1 holds up `yield_strength` kPa, the cantilever cost of one
+    /// default voxel is 2500 * 9.81 * 0.1 / 1000 = 2.45 kPa.
+    fn world(yield_strength: f32, voxels: impl IntoIterator<Item = IVec3>) -> VoxelWorld {
+        let mut world = VoxelWorld::default();
+        world.palette = vec![
+            Material::default(),
+            Material {
+                yield_strength,
+                ..default()
+            },
+        ];
+        for pos in voxels {
+            world.set_voxel(pos, 1);
+        }
+        world
+    }
+
+    fn islands(world: &VoxelWorld, min: IVec3, max: IVec3) -> Vec<VoxelIsland> {
+        find_unsupported_islands(world, min, max, &StructuralSettings::default())
+    }
+
+    fn positions(island: &VoxelIsland) -> Vec<IVec3> {
+        island.voxels.iter().map(|&(p, _)| p).collect()
+    }
+
+    fn cube(min: IVec3, size: i32) -> Vec<IVec3> {
+        let mut voxels = Vec::new();
+        for y in 0..size {
+            for z in 0..size {
+                for x in 0..size {
+                    voxels.push(min + IVec3::new(x, y, z));
+                }
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn floating_island_is_detected() {
+        let world = world(100.0, cube(IVec3::new(4, 10, 4), 2));
+        let found = islands(&world, IVec3::new(4, 10, 4), IVec3::new(5, 11, 5));
+        assert_eq!(found.len(), 1);
+        assert_eq!(positions(&found[0]), cube(IVec3::new(4, 10, 4), 2));
+        assert_eq!(found[0].min, IVec3::new(4, 10, 4));
+        assert_eq!(found[0].max, IVec3::new(5, 11, 5));
+    }
+
+    #[test]
+    fn grounded_pillar_stays() {
+        let world = world(100.0, (0..=40).map(|y| IVec3::new(0, y, 0)));
+        assert!(islands(&world, IVec3::ZERO, IVec3::new(0, 40, 0)).is_empty());
+    }
+
+    #[test]
+    fn long_overhang_breaks_off() {
+        // A budget of 10 kPa carries four cantilevered voxels, the fifth one is over.
+        let pillar = (0..=3).map(|y| IVec3::new(0, y, 0));
+        let arm = (1..=8).map(|x| IVec3::new(x, 3, 0));
+        let world = world(10.0, pillar.chain(arm));
+        let found = islands(&world, IVec3::new(0, 0, 0), IVec3::new(8, 3, 0));
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            positions(&found[0]),
+            (5..=8).map(|x| IVec3::new(x, 3, 0)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn short_overhang_holds() {
+        let pillar = (0..=3).map(|y| IVec3::new(0, y, 0));
+        let arm = (1..=4).map(|x| IVec3::new(x, 3, 0));
+        let world = world(10.0, pillar.chain(arm));
+        assert!(islands(&world, IVec3::ZERO, IVec3::new(4, 3, 0)).is_empty());
+    }
+
+    #[test]
+    fn islands_are_found_in_the_same_order_every_time() {
+        let mut voxels = cube(IVec3::new(0, 10, 0), 2);
+        voxels.extend(cube(IVec3::new(8, 12, 3), 2));
+        voxels.extend(cube(IVec3::new(-6, 20, -2), 1));
+        let world = world(100.0, voxels);
+        let (min, max) = (IVec3::new(-6, 10, -2), IVec3::new(9, 20, 4));
+        let run =
+            || -> Vec<Vec<IVec3>> { islands(&world, min, max).iter().map(positions).collect() };
+        let first = run();
+        assert_eq!(first.len(), 3);
+        for _ in 0..4 {
+            assert_eq!(run(), first);
+        }
+    }
+
+    /// Detaches the island like `solve_structures` and drops it like `CollapseMode::Drop`.
+    fn drop_island(world: &mut VoxelWorld, island: &VoxelIsland) -> i32 {
+        for &(pos, _) in &island.voxels {
+            world.set_voxel(pos, 0);
+        }
+        let fall = drop_distance(world, island, 0);
+        for &(pos, mat_id) in &island.voxels {
+            world.set_voxel(pos + IVec3::NEG_Y * fall, mat_id);
+        }
+        fall
+    }
+
+    #[test]
+    fn dropped_island_lands_on_the_ground() {
+        let mut world = world(100.0, cube(IVec3::new(2, 10, 2), 2));
+        let found = islands(&world, IVec3::new(2, 10, 2), IVec3::new(3, 11, 3));
+        assert_eq!(drop_island(&mut world, &found[0]), 10);
+        assert_eq!(
+            islands(&world, IVec3::new(2, 0, 2), IVec3::new(3, 1, 3)).len(),
+            0,
+            "landed island should be anchored"
+        );
+        for pos in cube(IVec3::new(2, 0, 2), 2) {
+            assert!(world.is_solid(pos), "voxel missing at {}", pos);
+        }
+    }
+
+    #[test]
+    fn dropped_island_lands_on_an_obstacle() {
+        let mut voxels = cube(IVec3::new(2, 10, 2), 2);
+        // A pillar under one corner of the island, its top is at y = 3.
+        voxels.extend((0..=3).map(|y| IVec3::new(3, y, 3)));
+        let mut world = world(100.0, voxels);
+        let found = islands(&world, IVec3::new(2, 10, 2), IVec3::new(3, 11, 3));
+        assert_eq!(found.len(), 1);
+        assert_eq!(drop_island(&mut world, &found[0]), 6);
+        assert!(world.is_solid(IVec3::new(2, 4, 2)));
+        assert!(!world.is_solid(IVec3::new(2, 3, 2)));
+    }
+}
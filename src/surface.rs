@@ -0,0 +1,126 @@
+use crate::compute::WriteTextureWorker;
+use crate::render::DisplayImage;
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+use bevy_app_compute::prelude::*;
+
+/// Width and height in texels of one atlas layer.
+pub const TEXTURE_SIZE: u32 = 16;
+pub const MAX_TEXTURE_LAYERS: u32 = 64;
+/// `TEXTURE_SIZE` wide, layers stacked top to bottom. `Material.texture_layer` indexes them.
+pub const TEXTURE_ATLAS: &str = "textures/voxel_atlas.png";
+
+/// Surface texture atlas, copied into the "texels" buffer of the ray marcher as packed rgba8.
+#[derive(Resource, Default)]
+pub struct SurfaceTextures {
+    pub atlas: Handle<Image>,
+    texels: Vec<u32>,
+}
+
+/// Packs the atlas one u32 per texel, in the byte order `unpack4x8unorm` reads. The shader
+/// multiplies texels with linear colors, so sRGB images are converted to linear here.
+pub fn pack_atlas(image: &Image) -> Option<Vec<u32>> {
+    let size = image.size();
+    if !matches!(
+        image.texture_descriptor.format,
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+    ) || size.x != TEXTURE_SIZE
+        || size.y % TEXTURE_SIZE != 0
+    {
+        println!(
+            "Surface atlas must be rgba8 and {} texels wide with {}x{} layers, got {:?} {}x{}",
+            TEXTURE_SIZE,
+            TEXTURE_SIZE,
+            TEXTURE_SIZE,
+            image.texture_descriptor.format,
+            size.x,
+            size.y
+        );
+        return None;
+    }
+    let layers = (size.y / TEXTURE_SIZE).min(MAX_TEXTURE_LAYERS);
+    let texel_count = (layers * TEXTURE_SIZE * TEXTURE_SIZE) as usize;
+    let data = image.data.as_ref()?;
+    let srgb = image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb;
+    let to_linear = |c: u8| {
+        let linear = Srgba::gamma_function(c as f32 / 255.0);
+        (linear * 255.0).round() as u8
+    };
+    Some(
+        data.chunks_exact(4)
+            .take(texel_count)
+            .map(|c| {
+                let [r, g, b] = [c[0], c[1], c[2]].map(|v| if srgb { to_linear(v) } else { v });
+                u32::from_le_bytes([r, g, b, c[3]])
+            })
+            .collect(),
+    )
+}
+
+pub fn load_surface_textures(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SurfaceTextures {
+        atlas: asset_server.load(TEXTURE_ATLAS),
+        texels: Vec::new(),
+    });
+}
+
+/// Uploads the atlas when it loads or changes on disk, and again whenever the compute worker is
+/// rebuilt with fresh buffers.
+pub fn upload_surface_textures(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut textures: ResMut<SurfaceTextures>,
+    images: Res<Assets<Image>>,
+    display_image: Res<DisplayImage>,
+    mut worker: ResMut<AppComputeWorker<WriteTextureWorker>>,
+) {
+    let atlas = textures.atlas.id();
+    let reloaded = events
+        .read()
+        .filter(|e| e.is_loaded_with_dependencies(atlas) || e.is_modified(atlas))
+        .count()
+        > 0;
+    if reloaded {
+        if let Some(texels) = images.get(atlas).and_then(pack_atlas) {
+            println!(
+                "Surface atlas loaded: {} layers",
+                texels.len() as u32 / (TEXTURE_SIZE * TEXTURE_SIZE)
+            );
+            textures.texels = texels;
+            // Layers past the atlas read as white, also overwriting those of a larger atlas
+            // loaded before.
+            let full = (MAX_TEXTURE_LAYERS * TEXTURE_SIZE * TEXTURE_SIZE) as usize;
+            textures.texels.resize(full, u32::MAX);
+        }
+    }
+    if !textures.texels.is_empty() && (reloaded || display_image.is_changed()) {
+        worker.write_slice("texels", &textures.texels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::RenderAssetUsages;
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    fn atlas(texel: [u8; 4], format: TextureFormat) -> Image {
+        let size = Extent3d {
+            width: TEXTURE_SIZE,
+            height: TEXTURE_SIZE * 2,
+            depth_or_array_layers: 1,
+        };
+        let usage = RenderAssetUsages::default();
+        Image::new_fill(size, TextureDimension::D2, &texel, format, usage)
+    }
+
+    #[test]
+    fn srgb_atlas_is_packed_linear() {
+        let texels = pack_atlas(&atlas([188, 255, 0, 77], TextureFormat::Rgba8UnormSrgb)).unwrap();
+        assert_eq!(texels.len(), (2 * TEXTURE_SIZE * TEXTURE_SIZE) as usize);
+        // sRGB 188 is about half intensity, alpha is linear already.
+        assert_eq!(texels[0].to_le_bytes(), [128, 255, 0, 77]);
+
+        let texels = pack_atlas(&atlas([188, 255, 0, 77], TextureFormat::Rgba8Unorm)).unwrap();
+        assert_eq!(texels[0].to_le_bytes(), [188, 255, 0, 77]);
+    }
+}
@@ -0,0 +1,482 @@
+use crate::render::VoxelCamera;
+use crate::structure::NEIGHBOURS;
+use crate::voxel_map::{VoxelWorld, VoxelsEdited, voxel_index};
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// `from` turns into `to` at or above its own `melting_temperature`.
+    Melt,
+    /// `from` turns into `to` once it cools below the melting temperature of `to`.
+    Freeze,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    pub from: u8,
+    pub to: u8,
+    pub kind: TransitionKind,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct ThermalSettings {
+    /// Simulation steps per second.
+    pub tick_rate: f32,
+    pub max_ticks_per_frame: u32,
+    /// Rate at which each face open to air pulls a voxel towards the ambient temperature.
+    pub air_cooling: f32,
+    /// Bricks closer than this to ambient everywhere are dropped from the field.
+    pub threshold: f32,
+    /// Freezing happens this far below the melting point so voxels don't flicker.
+    pub hysteresis: f32,
+    /// Material that flammable voxels turn into when they ignite, none disables fire.
+    pub fire_material: Option<u8>,
+    pub fire_temperature: f32,
+    /// Seconds a fire voxel burns before it disappears.
+    pub burn_time: f32,
+    /// Temperature applied by the heat tool.
+    pub torch_temperature: f32,
+    pub torch_radius: f32,
+    pub transitions: Vec<Transition>,
+}
+
+impl ThermalSettings {
+    /// Follows a palette change, `map` as returned by `VoxelWorld::set_palette`. Rules for
+    /// materials that were removed are dropped.
+    pub fn remap(&mut self, map: &[u8]) {
+        self.fire_material = self
+            .fire_material
+            .map(|id| map[id as usize])
+            .filter(|&id| id != 0);
+        self.transitions.retain_mut(|t| {
+            t.from = map[t.from as usize];
+            t.to = map[t.to as usize];
+            t.from != 0 && t.to != 0
+        });
+    }
+}
+
+impl Default for ThermalSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: 10.0,
+            max_ticks_per_frame: 2,
+            air_cooling: 0.02,
+            threshold: 1.0,
+            hysteresis: 50.0,
+            fire_material: None,
+            fire_temperature: 900.0,
+            burn_time: 3.0,
+            torch_temperature: 1500.0,
+            torch_radius: 3.0,
+            transitions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ThermalBrick {
+    pub temperature: [f32; 64],
+    /// Seconds left for burning voxels.
+    pub burn: [f32; 64],
+}
+
+/// Sparse temperature field, only bricks that differ from `ambient` are stored and ticked.
+#[derive(Resource)]
+pub struct ThermalField {
+    pub bricks: HashMap<IVec3, ThermalBrick>,
+    pub ambient: f32,
+}
+
+impl Default for ThermalField {
+    fn default() -> Self {
+        Self {
+            bricks: HashMap::default(),
+            ambient: 20.0,
+        }
+    }
+}
+
+impl ThermalField {
+    fn empty_brick(&self) -> ThermalBrick {
+        ThermalBrick {
+            temperature: [self.ambient; 64],
+            burn: [0.0; 64],
+        }
+    }
+
+    pub fn temperature(&self, pos: IVec3) -> f32 {
+        self.bricks
+            .get(&(pos >> 2))
+            .map_or(self.ambient, |b| b.temperature[voxel_index(pos)])
+    }
+
+    pub fn set_temperature(&mut self, pos: IVec3, temperature: f32) {
+        let key: IVec3 = pos >> 2;
+        if !self.bricks.contains_key(&key) && temperature == self.ambient {
+            return;
+        }
+        let empty = self.empty_brick();
+        self.bricks.entry(key).or_insert(empty).temperature[voxel_index(pos)] = temperature;
+    }
+
+    /// Keeps heat with voxels moved by the cellular simulation.
+    pub fn swap(&mut self, a: IVec3, b: IVec3) {
+        let (ta, tb) = (self.temperature(a), self.temperature(b));
+        let burn = |field: &Self, p: IVec3| {
+            field
+                .bricks
+                .get(&(p >> 2))
+                .map_or(0.0, |brick| brick.burn[voxel_index(p)])
+        };
+        let (ba, bb) = (burn(self, a), burn(self, b));
+        self.set_temperature(a, tb);
+        self.set_temperature(b, ta);
+        for (pos, value) in [(a, bb), (b, ba)] {
+            if let Some(brick) = self.bricks.get_mut(&(pos >> 2)) {
+                brick.burn[voxel_index(pos)] = value;
+            }
+        }
+    }
+}
+
+/// Material the voxel turns into at `temperature`, if any.
+fn transition(
+    world: &VoxelWorld,
+    settings: &ThermalSettings,
+    mat_id: u8,
+    temperature: f32,
+) -> Option<u8> {
+    let material = world.material(mat_id);
+    if let Some(fire) = settings.fire_material {
+        if mat_id != fire && temperature >= material.ignition_temperature {
+            return Some(fire);
+        }
+    }
+    settings
+        .transitions
+        .iter()
+        .filter(|t| t.from == mat_id)
+        .find(|t| match t.kind {
+            TransitionKind::Melt => temperature >= material.melting_temperature,
+            TransitionKind::Freeze => {
+                temperature < world.material(t.to).melting_temperature - settings.hysteresis
+            }
+        })
+        .map(|t| t.to)
+}
+
+/// Conducts heat for one step and applies burning and phase changes. Returns the bounds of
+/// the voxels whose material changed.
+fn tick(
+    world: &mut VoxelWorld,
+    field: &mut ThermalField,
+    settings: &ThermalSettings,
+    dt: f32,
+) -> Option<(IVec3, IVec3)> {
+    // Heat can flow into any existing neighbour brick, so those are stepped as well.
+    let mut keys: HashSet<IVec3> = field.bricks.keys().copied().collect();
+    for key in field.bricks.keys() {
+        for offset in NEIGHBOURS {
+            if world.get_brick_at((key + offset) << 2).is_some() {
+                keys.insert(key + offset);
+            }
+        }
+    }
+    let mut keys: Vec<IVec3> = keys.into_iter().collect();
+    keys.sort_by_key(|k| (k.y, k.z, k.x));
+
+    let mut next: HashMap<IVec3, ThermalBrick> = HashMap::default();
+    let mut changes = Vec::new();
+    for key in keys {
+        let origin = key << 2;
+        let Some(&voxels) = world.get_brick_at(origin) else {
+            continue;
+        };
+        let old = field.bricks.get(&key);
+        let mut brick = field.empty_brick();
+        let mut keep = false;
+
+        for i in 0..64 {
+            let mat_id = voxels.voxels[i];
+            if mat_id == 0 {
+                continue;
+            }
+            let pos = origin + IVec3::new(i as i32 & 3, (i as i32 >> 4) & 3, (i as i32 >> 2) & 3);
+            let material = world.material(mat_id);
+            let mut temperature = old.map_or(field.ambient, |b| b.temperature[i]);
+            let mut burn = old.map_or(0.0, |b| b.burn[i]);
+
+            if Some(mat_id) == settings.fire_material {
+                // Fire placed by an edit or a palette change has not started burning yet.
+                if burn <= 0.0 {
+                    burn = settings.burn_time;
+                }
+                burn -= dt;
+                temperature = settings.fire_temperature;
+                if burn <= 0.0 {
+                    changes.push((pos, 0));
+                }
+            } else {
+                let mut flux = 0.0;
+                for offset in NEIGHBOURS {
+                    let neighbour = pos + offset;
+                    let n_id = world.get_voxel(neighbour);
+                    if n_id == 0 {
+                        flux += settings.air_cooling * (field.ambient - temperature);
+                    } else {
+                        let k = material.conductivity.min(world.material(n_id).conductivity);
+                        flux += k * (field.temperature(neighbour) - temperature);
+                    }
+                }
+                // Explicit steps are only stable while no voxel gives away more than it has.
+                temperature += flux * dt.min(1.0 / 7.0);
+                if let Some(to) = transition(world, settings, mat_id, temperature) {
+                    changes.push((pos, to));
+                    if Some(to) == settings.fire_material {
+                        burn = settings.burn_time;
+                    }
+                }
+            }
+
+            brick.temperature[i] = temperature;
+            brick.burn[i] = burn;
+            keep |= burn > 0.0 || (temperature - field.ambient).abs() > settings.threshold;
+        }
+        if keep {
+            next.insert(key, brick);
+        }
+    }
+    field.bricks = next;
+
+    let mut bounds: Option<(IVec3, IVec3)> = None;
+    for (pos, to) in changes {
+        world.set_voxel(pos, to);
+        let (min, max) = bounds.unwrap_or((pos, pos));
+        bounds = Some((min.min(pos), max.max(pos)));
+    }
+    bounds
+}
+
+/// Starts ticking fire voxels placed by edits, they only burn while their brick is in the
+/// field.
+pub fn activate_edited_fire(
+    mut edits: EventReader<VoxelsEdited>,
+    world: Res<VoxelWorld>,
+    mut field: ResMut<ThermalField>,
+    settings: Res<ThermalSettings>,
+) {
+    let Some(fire) = settings.fire_material else {
+        edits.clear();
+        return;
+    };
+    for edit in edits.read() {
+        world.for_each_solid_in(edit.min, edit.max, |pos, mat_id| {
+            if mat_id == fire {
+                field.set_temperature(pos, settings.fire_temperature);
+            }
+        });
+    }
+}
+
+pub fn simulate_thermal(
+    time: Res<Time>,
+    mut world: ResMut<VoxelWorld>,
+    mut field: ResMut<ThermalField>,
+    mut edits: EventWriter<VoxelsEdited>,
+    settings: Res<ThermalSettings>,
+    mut accumulator: Local<f32>,
+) {
+    if field.bricks.is_empty() {
+        *accumulator = 0.0;
+        return;
+    }
+    let step = 1.0 / settings.tick_rate;
+    *accumulator =
+        (*accumulator + time.delta_secs()).min(step * settings.max_ticks_per_frame as f32);
+
+    while *accumulator >= step {
+        *accumulator -= step;
+        if let Some((min, max)) = tick(world.bypass_change_detection(), &mut field, &settings, step)
+        {
+            world.set_changed();
+            edits.write(VoxelsEdited { min, max });
+        }
+    }
+}
+
+/// T heats the voxels around the surface under the crosshair.
+pub fn heat_at_cursor(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    camera_q: Query<&Transform, With<VoxelCamera>>,
+    world: Res<VoxelWorld>,
+    mut field: ResMut<ThermalField>,
+    settings: Res<ThermalSettings>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+    let Ok(transform) = camera_q.single() else {
+        return;
+    };
+    let Some(hit) = world.raycast(transform.translation, *transform.forward(), 1024.0) else {
+        return;
+    };
+    let center = hit.pos.as_vec3() + 0.5;
+    let radius = settings.torch_radius;
+    let lo = (center - radius).floor().as_ivec3();
+    let hi = (center + radius).ceil().as_ivec3();
+    world.for_each_solid_in(lo, hi, |pos, _| {
+        if (pos.as_vec3() + 0.5).distance(center) <= radius {
+            let temperature = field.temperature(pos).max(settings.torch_temperature);
+            field.set_temperature(pos, temperature);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Material;
+
+    const STONE: u8 = 1;
+    const WOOD: u8 = 2;
+    const FIRE: u8 = 3;
+    const ICE: u8 = 4;
+    const WATER: u8 = 5;
+
+    fn world() -> VoxelWorld {
+        VoxelWorld {
+            palette: vec![
+                Material::default(),
+                Material::default(),
+                Material {
+                    ignition_temperature: 300.0,
+                    ..default()
+                },
+                Material::default(),
+                Material {
+                    melting_temperature: 0.0,
+                    ..default()
+                },
+                Material::default(),
+            ],
+            ..default()
+        }
+    }
+
+    fn settings() -> ThermalSettings {
+        ThermalSettings {
+            air_cooling: 0.0,
+            fire_material: Some(FIRE),
+            transitions: vec![
+                Transition {
+                    from: ICE,
+                    to: WATER,
+                    kind: TransitionKind::Melt,
+                },
+                Transition {
+                    from: WATER,
+                    to: ICE,
+                    kind: TransitionKind::Freeze,
+                },
+            ],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn heat_conducts_through_touching_voxels_only() {
+        let mut world = world();
+        for x in [0, 1, 3] {
+            world.set_voxel(IVec3::new(x, 0, 0), STONE);
+        }
+        let mut field = ThermalField::default();
+        field.set_temperature(IVec3::ZERO, 1000.0);
+        for _ in 0..10 {
+            tick(&mut world, &mut field, &settings(), 0.1);
+        }
+        let (hot, warm) = (field.temperature(IVec3::ZERO), field.temperature(IVec3::X));
+        assert!(hot < 1000.0 && warm > 100.0, "{hot} {warm}");
+        // Without air cooling the heat is only moved around.
+        assert!((hot + warm - 1020.0).abs() < 0.1, "{hot} {warm}");
+        assert_eq!(field.temperature(IVec3::new(3, 0, 0)), field.ambient);
+    }
+
+    #[test]
+    fn fire_spreads_along_wood_and_burns_out() {
+        let mut world = world();
+        for x in 0..6 {
+            world.set_voxel(IVec3::new(x, 0, 0), WOOD);
+        }
+        world.set_voxel(IVec3::ZERO, FIRE);
+        let settings = settings();
+        let mut field = ThermalField::default();
+        field.set_temperature(IVec3::ZERO, settings.fire_temperature);
+
+        tick(&mut world, &mut field, &settings, 0.1);
+        assert_eq!(
+            world.get_voxel(IVec3::ZERO),
+            FIRE,
+            "new fire burns for burn_time"
+        );
+        let mut reached_end = false;
+        for _ in 0..1000 {
+            tick(&mut world, &mut field, &settings, 0.1);
+            reached_end |= world.get_voxel(IVec3::new(5, 0, 0)) == FIRE;
+        }
+        assert!(reached_end);
+        assert!((0..6).all(|x| !world.is_solid(IVec3::new(x, 0, 0))));
+    }
+
+    #[test]
+    fn edited_fire_is_added_to_the_field() {
+        let mut world = world();
+        world.set_voxel(IVec3::new(40, 0, 0), FIRE);
+        let mut app = App::new();
+        app.add_event::<VoxelsEdited>()
+            .insert_resource(world)
+            .init_resource::<ThermalField>()
+            .insert_resource(settings())
+            .add_systems(Update, activate_edited_fire);
+        app.world_mut().send_event(VoxelsEdited {
+            min: IVec3::new(38, 0, 0),
+            max: IVec3::new(42, 0, 0),
+        });
+        app.update();
+        let field = app.world().resource::<ThermalField>();
+        assert_eq!(
+            field.temperature(IVec3::new(40, 0, 0)),
+            settings().fire_temperature
+        );
+    }
+
+    #[test]
+    fn melting_and_freezing_points_are_apart() {
+        let (world, settings) = (world(), settings());
+        assert_eq!(transition(&world, &settings, ICE, -10.0), None);
+        assert_eq!(transition(&world, &settings, ICE, 0.0), Some(WATER));
+        assert_eq!(transition(&world, &settings, WATER, -10.0), None);
+        assert_eq!(transition(&world, &settings, WATER, -60.0), Some(ICE));
+    }
+
+    #[test]
+    fn remap_follows_the_palette_and_drops_removed_rules() {
+        let mut settings = settings();
+        // Wood is removed, everything after it moves down one slot.
+        settings.remap(&[0, 1, 0, 2, 3, 4]);
+        assert_eq!(settings.fire_material, Some(2));
+        let rules: Vec<(u8, u8)> = settings
+            .transitions
+            .iter()
+            .map(|t| (t.from, t.to))
+            .collect();
+        assert_eq!(rules, [(3, 4), (4, 3)]);
+
+        settings.remap(&[0, 1, 0, 2, 3]);
+        assert_eq!(settings.fire_material, None);
+        settings.remap(&[0, 1, 2, 0, 3]);
+        assert!(settings.transitions.is_empty());
+    }
+}
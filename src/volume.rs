@@ -0,0 +1,425 @@
+use crate::config::{Brick, Material};
+use crate::structure::NEIGHBOURS;
+use bevy::math::{IVec3, Vec3};
+use bevy::prelude::Mesh;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_asset::RenderAssetUsages;
+use std::io;
+
+const PREFAB_MAGIC: &[u8; 4] = b"VXPF";
+/// Version 2 added the palette names, version 1 files are still read.
+const PREFAB_VERSION: u32 = 2;
+/// Largest volume a prefab file may declare, one byte per voxel once loaded.
+pub const MAX_PREFAB_VOXELS: usize = 1 << 26;
+
+/// A small standalone voxel grid stored as a dense array of bricks, using the same
+/// voxel layout as `VoxelWorld` bricks.
+#[derive(Clone, Debug)]
+pub struct VoxelVolume {
+    pub size: IVec3,
+    pub bricks: Vec<Brick>,
+}
+
+impl VoxelVolume {
+    pub fn new(size: IVec3) -> Self {
+        let dims: IVec3 = (size + 3) >> 2;
+        Self {
+            size,
+            bricks: vec![Brick { voxels: [0; 64] }; (dims.x * dims.y * dims.z) as usize],
+        }
+    }
+
+    fn brick_dims(&self) -> IVec3 {
+        (self.size + 3) >> 2
+    }
+
+    fn locate(&self, pos: IVec3) -> Option<(usize, usize)> {
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(self.size).any() {
+            return None;
+        }
+        let dims = self.brick_dims();
+        let brick_pos: IVec3 = pos >> 2;
+        let brick = brick_pos.x + brick_pos.y * dims.x + brick_pos.z * dims.x * dims.y;
+        let v_local: IVec3 = pos & 3;
+        let voxel = v_local.x + v_local.z * 4 + v_local.y * 16;
+        Some((brick as usize, voxel as usize))
+    }
+
+    pub fn get(&self, pos: IVec3) -> u8 {
+        self.locate(pos)
+            .map_or(0, |(brick, voxel)| self.bricks[brick].voxels[voxel])
+    }
+
+    pub fn set(&mut self, pos: IVec3, mat_id: u8) {
+        if let Some((brick, voxel)) = self.locate(pos) {
+            self.bricks[brick].voxels[voxel] = mat_id;
+        }
+    }
+
+    /// Solid voxels in x, z, y order.
+    pub fn iter_solid(&self) -> impl Iterator<Item = (IVec3, u8)> + '_ {
+        (0..self.size.y).flat_map(move |y| {
+            (0..self.size.z).flat_map(move |z| {
+                (0..self.size.x).filter_map(move |x| {
+                    let pos = IVec3::new(x, y, z);
+                    let mat_id = self.get(pos);
+                    (mat_id != 0).then_some((pos, mat_id))
+                })
+            })
+        })
+    }
+
+    pub fn is_surface(&self, pos: IVec3) -> bool {
+        NEIGHBOURS.iter().any(|&offset| self.get(pos + offset) == 0)
+    }
+
+    /// Copy turned by `turns` quarter turns around `axis` (0 = x, 1 = y, 2 = z).
+    pub fn rotated(&self, axis: usize, turns: i32) -> Self {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut volume = self.clone();
+        for _ in 0..turns.rem_euclid(4) {
+            let mut size = volume.size;
+            size[u] = volume.size[v];
+            size[v] = volume.size[u];
+            let mut turned = Self::new(size);
+            for (pos, mat_id) in volume.iter_solid() {
+                let mut to = pos;
+                to[u] = volume.size[v] - 1 - pos[v];
+                to[v] = pos[u];
+                turned.set(to, mat_id);
+            }
+            volume = turned;
+        }
+        volume
+    }
+
+    pub fn mirrored(&self, axis: usize) -> Self {
+        let mut mirrored = Self::new(self.size);
+        for (pos, mat_id) in self.iter_solid() {
+            let mut to = pos;
+            to[axis] = self.size[axis] - 1 - pos[axis];
+            mirrored.set(to, mat_id);
+        }
+        mirrored
+    }
+
+    /// Replaces every material id `id` with `map[id]`.
+    pub fn remap(&mut self, map: &[u8]) {
+        for brick in &mut self.bricks {
+            for voxel in &mut brick.voxels {
+                *voxel = map[*voxel as usize];
+            }
+        }
+    }
+
+    /// Prefab file format: `VXPF`, version, size as three i32, the palette names as a u16
+    /// count of (u8 length, utf-8) strings and the voxels in x, z, y order as run length
+    /// pairs of (u16 count, u8 material). Integers are little endian.
+    pub fn to_bytes(&self, palette_names: &[String]) -> Vec<u8> {
+        let mut bytes = Vec::from(*PREFAB_MAGIC);
+        bytes.extend_from_slice(&PREFAB_VERSION.to_le_bytes());
+        for axis in 0..3 {
+            bytes.extend_from_slice(&self.size[axis].to_le_bytes());
+        }
+        bytes.extend_from_slice(&(palette_names.len() as u16).to_le_bytes());
+        for name in palette_names {
+            let name = &name.as_bytes()[..name.len().min(255)];
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name);
+        }
+
+        let mut run: Option<(u16, u8)> = None;
+        for y in 0..self.size.y {
+            for z in 0..self.size.z {
+                for x in 0..self.size.x {
+                    let mat_id = self.get(IVec3::new(x, y, z));
+                    run = match run {
+                        Some((count, id)) if id == mat_id && count < u16::MAX => {
+                            Some((count + 1, id))
+                        }
+                        Some((count, id)) => {
+                            bytes.extend_from_slice(&count.to_le_bytes());
+                            bytes.push(id);
+                            Some((1, mat_id))
+                        }
+                        None => Some((1, mat_id)),
+                    };
+                }
+            }
+        }
+        if let Some((count, id)) = run {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.push(id);
+        }
+        bytes
+    }
+
+    /// Returns the volume and the names of the palette its ids refer to, empty for files
+    /// saved before names were stored.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, Vec<String>)> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 20 || &bytes[0..4] != PREFAB_MAGIC {
+            return Err(invalid("not a voxel prefab"));
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let version = read_u32(4);
+        if version == 0 || version > PREFAB_VERSION {
+            return Err(invalid("unsupported prefab version"));
+        }
+        let size = IVec3::new(read_u32(8) as i32, read_u32(12) as i32, read_u32(16) as i32);
+        // Checked before anything is allocated, the header is untrusted.
+        let total = (size.x as usize)
+            .checked_mul(size.y as usize)
+            .and_then(|n| n.checked_mul(size.z as usize))
+            .filter(|&n| size.cmpgt(IVec3::ZERO).all() && n <= MAX_PREFAB_VOXELS)
+            .ok_or_else(|| invalid("prefab size out of range"))?;
+
+        let mut at = 20;
+        let mut palette_names = Vec::new();
+        if version >= 2 {
+            let count = bytes
+                .get(at..at + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .ok_or_else(|| invalid("truncated prefab"))?;
+            at += 2;
+            for _ in 0..count {
+                let len = *bytes.get(at).ok_or_else(|| invalid("truncated prefab"))? as usize;
+                let name = bytes
+                    .get(at + 1..at + 1 + len)
+                    .ok_or_else(|| invalid("truncated prefab"))?;
+                palette_names.push(String::from_utf8_lossy(name).into_owned());
+                at += 1 + len;
+            }
+        }
+
+        let mut volume = Self::new(size);
+        let mut index = 0;
+        for pair in bytes[at..].chunks(3) {
+            let [lo, hi, mat_id] = pair else {
+                return Err(invalid("truncated prefab"));
+            };
+            let count = u16::from_le_bytes([*lo, *hi]) as usize;
+            if index + count > total {
+                return Err(invalid("prefab has too many voxels"));
+            }
+            for i in index..index + count {
+                let i = i as i32;
+                let x = i % size.x;
+                let z = (i / size.x) % size.z;
+                let y = i / (size.x * size.z);
+                volume.set(IVec3::new(x, y, z), *mat_id);
+            }
+            index += count;
+        }
+        if index != total {
+            return Err(invalid("truncated prefab"));
+        }
+        Ok((volume, palette_names))
+    }
+
+    /// Builds a mesh of the exposed voxel faces, colored by palette, with `offset` added to
+    /// every vertex.
+    pub fn build_mesh(&self, palette: &[Material], offset: Vec3) -> Mesh {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut colors = Vec::new();
+        let mut indices = Vec::new();
+
+        for (pos, mat_id) in self.iter_solid() {
+            let color = palette
+                .get(mat_id as usize)
+                .copied()
+                .unwrap_or_default()
+                .color;
+            for normal in NEIGHBOURS {
+                if self.get(pos + normal) != 0 {
+                    continue;
+                }
+                let n = normal.as_vec3();
+                // Two tangents so the quad winds counter-clockwise seen from outside.
+                let u = if normal.x != 0 {
+                    Vec3::Y
+                } else if normal.y != 0 {
+                    Vec3::Z
+                } else {
+                    Vec3::X
+                };
+                let v = n.cross(u);
+                let center = pos.as_vec3() + Vec3::splat(0.5) + n * 0.5 + offset;
+
+                let base = positions.len() as u32;
+                for (su, sv) in [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)] {
+                    positions.push((center + u * su + v * sv).to_array());
+                    normals.push(n.to_array());
+                    colors.push([color[0], color[1], color[2], 1.0]);
+                }
+                indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+            }
+        }
+
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors)
+        .with_inserted_indices(Indices::U32(indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(size: IVec3, voxels: &[(IVec3, u8)]) -> VoxelVolume {
+        let mut volume = VoxelVolume::new(size);
+        for &(pos, mat_id) in voxels {
+            volume.set(pos, mat_id);
+        }
+        volume
+    }
+
+    fn solids(volume: &VoxelVolume) -> (IVec3, Vec<(IVec3, u8)>) {
+        let mut voxels: Vec<(IVec3, u8)> = volume.iter_solid().collect();
+        voxels.sort_by_key(|&(p, _)| p.to_array());
+        (volume.size, voxels)
+    }
+
+    fn sample() -> VoxelVolume {
+        volume(
+            IVec3::new(3, 2, 5),
+            &[
+                (IVec3::new(0, 0, 0), 1),
+                (IVec3::new(2, 1, 0), 2),
+                (IVec3::new(1, 0, 4), 3),
+            ],
+        )
+    }
+
+    #[test]
+    fn rotating_a_quarter_turn_moves_voxels() {
+        // Around y, x becomes z counted from the far side and z becomes x.
+        let turned = sample().rotated(1, 1);
+        assert_eq!(
+            solids(&turned),
+            solids(&volume(
+                IVec3::new(5, 2, 3),
+                &[
+                    (IVec3::new(0, 0, 2), 1),
+                    (IVec3::new(0, 1, 0), 2),
+                    (IVec3::new(4, 0, 1), 3),
+                ],
+            ))
+        );
+    }
+
+    #[test]
+    fn rotations_compose() {
+        let sample = sample();
+        for axis in 0..3 {
+            assert_eq!(solids(&sample.rotated(axis, 4)), solids(&sample));
+            assert_eq!(
+                solids(&sample.rotated(axis, 1).rotated(axis, 3)),
+                solids(&sample)
+            );
+            assert_eq!(
+                solids(&sample.rotated(axis, -1)),
+                solids(&sample.rotated(axis, 3))
+            );
+        }
+    }
+
+    #[test]
+    fn mirroring_flips_one_axis() {
+        let sample = sample();
+        assert_eq!(
+            solids(&sample.mirrored(2)),
+            solids(&volume(
+                IVec3::new(3, 2, 5),
+                &[
+                    (IVec3::new(0, 0, 4), 1),
+                    (IVec3::new(2, 1, 4), 2),
+                    (IVec3::new(1, 0, 0), 3),
+                ],
+            ))
+        );
+        for axis in 0..3 {
+            assert_eq!(
+                solids(&sample.mirrored(axis).mirrored(axis)),
+                solids(&sample)
+            );
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let names = vec!["air".to_string(), "stone".to_string(), "wood".to_string()];
+        // Long runs of one material have to be split at u16::MAX.
+        let mut large = VoxelVolume::new(IVec3::new(64, 20, 64));
+        for (i, brick) in large.bricks.iter_mut().enumerate() {
+            brick.voxels = [(i % 3) as u8; 64];
+        }
+        for volume in [sample(), large, VoxelVolume::new(IVec3::ONE)] {
+            let bytes = volume.to_bytes(&names);
+            let (loaded, loaded_names) = VoxelVolume::from_bytes(&bytes).unwrap();
+            assert_eq!(solids(&loaded), solids(&volume));
+            assert_eq!(loaded_names, names);
+        }
+    }
+
+    fn header(size: [u32; 3]) -> Vec<u8> {
+        let mut bytes = Vec::from(*PREFAB_MAGIC);
+        bytes.extend_from_slice(&PREFAB_VERSION.to_le_bytes());
+        for axis in size {
+            bytes.extend_from_slice(&axis.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        for size in [
+            [4096, 4096, 4096],
+            [u32::MAX, 2, 2],
+            [1 << 20, 1 << 20, 1],
+            [0, 1, 1],
+        ] {
+            let err = VoxelVolume::from_bytes(&header(size)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn version_1_files_load_without_names() {
+        // Version 1 has no names table, the runs follow the size directly.
+        let mut bytes = Vec::from(*PREFAB_MAGIC);
+        for value in [1u32, 2, 1, 2] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for (count, mat_id) in [(1u16, 3u8), (2, 0), (1, 5)] {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.push(mat_id);
+        }
+        let (loaded, names) = VoxelVolume::from_bytes(&bytes).unwrap();
+        assert!(names.is_empty());
+        let expected = volume(
+            IVec3::new(2, 1, 2),
+            &[(IVec3::new(0, 0, 0), 3), (IVec3::new(1, 0, 1), 5)],
+        );
+        assert_eq!(solids(&loaded), solids(&expected));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = sample().to_bytes(&[]);
+        for len in [0, 10, 21, bytes.len() - 1] {
+            assert!(
+                VoxelVolume::from_bytes(&bytes[..len]).is_err(),
+                "length {}",
+                len
+            );
+        }
+    }
+}
@@ -1,17 +1,78 @@
 use crate::config::{Brick, Material, Node};
 use bevy::asset::AssetId;
-use bevy::math::IVec3;
+use bevy::math::{IVec3, Vec3};
 use bevy::platform::collections::HashMap;
-use bevy::prelude::{Res, ResMut, Resource};
+use bevy::prelude::{DetectChanges, Event, Res, ResMut, Resource};
+
+/// Sectors are 64^3 voxels, the leaves of the top level tree.
+pub const SECTOR_SCALE: i32 = 6;
+
+/// The SVO addresses voxels in `0..WORLD_SIZE` on every axis, the 21 bits per axis of
+/// `get_morton_key`. Voxels outside of it are stored but not rendered correctly.
+pub const WORLD_SIZE: i32 = 1 << 21;
+
+pub fn in_svo_bounds(pos: IVec3) -> bool {
+    pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(WORLD_SIZE)).all()
+}
 
 pub struct Sector {
+    /// Add and remove bricks with `insert_brick` and `remove_brick`, which keep the cell counts
+    /// behind `occupancy` current.
     pub bricks: HashMap<u32, Brick>,
+    /// Number of bricks in each 16^3 cell, in child slot order.
+    cell_bricks: [u8; 64],
+}
+
+impl Default for Sector {
+    fn default() -> Self {
+        Self {
+            bricks: HashMap::default(),
+            cell_bricks: [0; 64],
+        }
+    }
+}
+
+impl Sector {
+    /// Child mask of the 16^3 cells holding at least one brick, the same cells and layout as
+    /// the children of the sector's root node in the SVO.
+    pub fn occupancy(&self) -> u64 {
+        self.cell_bricks
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .fold(0, |mask, (slot, _)| mask | 1 << slot)
+    }
+
+    pub fn insert_brick(&mut self, brick_idx: u32, brick: Brick) -> Option<Brick> {
+        let previous = self.bricks.insert(brick_idx, brick);
+        if previous.is_none() {
+            self.cell_bricks[cell_slot(brick_idx)] += 1;
+        }
+        previous
+    }
+
+    pub fn remove_brick(&mut self, brick_idx: u32) -> Option<Brick> {
+        let removed = self.bricks.remove(&brick_idx);
+        if removed.is_some() {
+            self.cell_bricks[cell_slot(brick_idx)] -= 1;
+        }
+        removed
+    }
+}
+
+/// Sent after voxels inside `min..=max` were changed.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct VoxelsEdited {
+    pub min: IVec3,
+    pub max: IVec3,
 }
 
 #[derive(Resource, Default)]
 pub struct VoxelWorld {
     pub sectors: HashMap<IVec3, Sector>,
     pub palette: Vec<Material>,
+    /// Names of the `palette` entries, set by `set_palette`.
+    pub palette_names: Vec<String>,
 }
 
 #[derive(Resource, Default)]
@@ -59,13 +120,18 @@ pub fn build_chunk_tree(
             }
 
             let child_ptr = leaf_data.len() as u32;
+            let mut color = Vec3::ZERO;
             for &mat_id in &brick.voxels {
                 if mat_id != 0 {
                     leaf_data.push(mat_id as u32);
+                    color += Vec3::from_array(world.material(mat_id).color);
                 }
             }
 
-            return Some(Node::new(child_ptr, true, mask));
+            let count = mask.count_ones() as f32;
+            let mut node = Node::new(child_ptr, true, mask);
+            node.set_lod(color / count, count);
+            return Some(node);
         }
         return None;
     }
@@ -93,9 +159,11 @@ pub fn build_chunk_tree(
     }
 
     let child_start_ptr = nodes.len() as u32;
+    let mut node = Node::new(child_start_ptr, false, current_node_mask);
+    node.average_lod(&children_results);
     nodes.extend(children_results);
 
-    Some(Node::new(child_start_ptr, false, current_node_mask))
+    Some(node)
 }
 
 pub fn build_tlas(
@@ -110,7 +178,10 @@ pub fn build_tlas(
     let mut layer_nodes = chunk_roots;
     let mut current_scale = min_scale + 2;
 
-    while (current_scale <= 21 && layer_nodes.len() > 1) {
+    // Stopping at a single node is not enough: a lone sector away from the origin would become
+    // the root and the shader, which maps the root to [1, 2), would draw it at the origin.
+    // Keep adding levels until the remaining node's key is 0, i.e. its bounds contain the origin.
+    while (current_scale <= 21 && (layer_nodes.len() > 1 || layer_nodes[0].0 != 0)) {
         let mut next_layer = Vec::new();
         let mut i = 0;
 
@@ -154,9 +225,10 @@ pub fn build_tlas(
                 i += 1;
             }
 
+            let mut parent = Node::new(child_start_ptr, false, parent_pop_mask);
+            parent.average_lod(&children_to_push);
             node_pool.extend(children_to_push);
 
-            let parent = Node::new(child_start_ptr, false, parent_pop_mask);
             next_layer.push((parent_pos_key, parent));
         }
 
@@ -185,7 +257,6 @@ impl VoxelWorld {
                 sector_pos * 64,
             ) {
                 let morton_key = get_morton_key(sector_pos) << 18;
-                println!("Sector: {} -> Key: {:b}", sector_pos, morton_key);
                 chunk_roots.push((morton_key, root));
             }
         }
@@ -196,20 +267,187 @@ impl VoxelWorld {
 
         chunk_roots.sort_by_key(|k| k.0);
 
-        let (global_root, final_scale) =
-            build_tlas(chunk_roots, &mut storage.nodes, storage.tree_scale as i32);
+        let (global_root, final_scale) = build_tlas(chunk_roots, &mut storage.nodes, SECTOR_SCALE);
         storage.nodes[0] = global_root;
         storage.tree_scale = final_scale;
-        println!("SVO Generated. Final Scale: {}", final_scale);
     }
 
     pub fn get_brick_at(&self, pos: IVec3) -> Option<&Brick> {
         let sector_pos = pos >> 6;
         if let Some(sector) = self.sectors.get(&sector_pos) {
-            let local_pos: IVec3 = (pos >> 2) & 15;
-            let brick_idx = (local_pos.x + local_pos.y * 16 + local_pos.z * 256) as u32;
-            return sector.bricks.get(&brick_idx);
+            return sector.bricks.get(&brick_index(pos));
         }
         None
     }
+
+    pub fn get_voxel(&self, pos: IVec3) -> u8 {
+        self.get_brick_at(pos)
+            .map_or(0, |brick| brick.voxels[voxel_index(pos)])
+    }
+
+    pub fn is_solid(&self, pos: IVec3) -> bool {
+        self.get_voxel(pos) != 0
+    }
+
+    pub fn material(&self, mat_id: u8) -> Material {
+        self.palette
+            .get(mat_id as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Writes a single voxel and returns the previous material id.
+    /// Bricks and sectors are created on demand and dropped again once empty.
+    pub fn set_voxel(&mut self, pos: IVec3, mat_id: u8) -> u8 {
+        let sector_pos = pos >> 6;
+        if mat_id == 0 && !self.sectors.contains_key(&sector_pos) {
+            return 0;
+        }
+
+        let sector = self.sectors.entry(sector_pos).or_default();
+        let brick_idx = brick_index(pos);
+        let previous = match sector.bricks.get_mut(&brick_idx) {
+            Some(brick) => std::mem::replace(&mut brick.voxels[voxel_index(pos)], mat_id),
+            None if mat_id != 0 => {
+                let mut brick = Brick { voxels: [0; 64] };
+                brick.voxels[voxel_index(pos)] = mat_id;
+                sector.insert_brick(brick_idx, brick);
+                0
+            }
+            None => 0,
+        };
+
+        if mat_id == 0 && previous != 0 {
+            if sector
+                .bricks
+                .get(&brick_idx)
+                .is_some_and(|b| b.pack_bits_64() == 0)
+            {
+                sector.remove_brick(brick_idx);
+            }
+            if sector.bricks.is_empty() {
+                self.sectors.remove(&sector_pos);
+            }
+        }
+        previous
+    }
+
+    /// Replaces the whole brick containing `pos`, empty bricks are dropped.
+    pub fn set_brick(&mut self, pos: IVec3, brick: Option<Brick>) {
+        let sector_pos = pos >> 6;
+        let brick_idx = brick_index(pos);
+        match brick.filter(|b| b.pack_bits_64() != 0) {
+            Some(brick) => {
+                self.sectors
+                    .entry(sector_pos)
+                    .or_default()
+                    .insert_brick(brick_idx, brick);
+            }
+            None => {
+                if let Some(sector) = self.sectors.get_mut(&sector_pos) {
+                    sector.remove_brick(brick_idx);
+                    if sector.bricks.is_empty() {
+                        self.sectors.remove(&sector_pos);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Index of the brick containing `pos` inside its sector.
+pub fn brick_index(pos: IVec3) -> u32 {
+    let local_pos: IVec3 = (pos >> 2) & 15;
+    (local_pos.x + local_pos.y * 16 + local_pos.z * 256) as u32
+}
+
+/// Slot of the 16^3 cell containing brick `brick_idx` inside its sector, in child slot order.
+pub fn cell_slot(brick_idx: u32) -> usize {
+    let (x, y, z) = (brick_idx & 15, (brick_idx >> 4) & 15, (brick_idx >> 8) & 15);
+    ((x >> 2) + (z >> 2) * 4 + (y >> 2) * 16) as usize
+}
+
+/// Index of `pos` inside its brick, same layout as the node child slots.
+pub fn voxel_index(pos: IVec3) -> usize {
+    let v_local: IVec3 = pos & 3;
+    (v_local.x + v_local.z * 4 + v_local.y * 16) as usize
+}
+
+pub fn rebuild_svo(world: Res<VoxelWorld>, mut svo: ResMut<SvoStorage>) {
+    if (world.is_changed()) {
+        world.generate_svo(&mut svo);
+        println!(
+            "Generated SVO: {} nodes, scale {}",
+            svo.nodes.len(),
+            svo.tree_scale
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Material at `pos`, descending from the root the way the ray marcher does.
+    fn lookup(svo: &SvoStorage, pos: IVec3) -> u32 {
+        let mut node = svo.nodes[0];
+        let mut scale = svo.tree_scale as i32;
+        loop {
+            let slot: IVec3 = (pos >> (scale - 2)) & 3;
+            let i = (slot.x + slot.z * 4 + slot.y * 16) as u32;
+            let mask = node.packed_data[1] as u64 | (node.packed_data[2] as u64) << 32;
+            if mask & (1 << i) == 0 {
+                return 0;
+            }
+            let rank = (mask & ((1 << i) - 1)).count_ones() as usize;
+            let index = (node.packed_data[0] >> 2) as usize + rank;
+            if node.packed_data[0] & 1 != 0 {
+                return svo.leaf_data[index];
+            }
+            node = svo.nodes[index];
+            scale -= 2;
+        }
+    }
+
+    #[test]
+    fn sector_occupancy_follows_bricks() {
+        let mut world = VoxelWorld::default();
+        world.set_voxel(IVec3::new(1, 2, 3), 5);
+        world.set_voxel(IVec3::new(2, 2, 3), 5);
+        world.set_voxel(IVec3::new(17, 40, 63), 5);
+        let sector = &world.sectors[&IVec3::ZERO];
+        assert_eq!(sector.occupancy(), 1 | 1 << (1 + 3 * 4 + 2 * 16));
+
+        // The cell stays occupied until its last brick is gone.
+        world.set_voxel(IVec3::new(1, 2, 3), 0);
+        assert_eq!(world.sectors[&IVec3::ZERO].occupancy() & 1, 1);
+        world.set_brick(IVec3::new(2, 2, 3), None);
+        assert_eq!(
+            world.sectors[&IVec3::ZERO].occupancy(),
+            1 << (1 + 3 * 4 + 2 * 16)
+        );
+        world.set_voxel(IVec3::new(17, 40, 63), 0);
+        assert!(world.sectors.is_empty());
+    }
+
+    #[test]
+    fn lone_sector_away_from_origin_gets_a_root_at_the_origin() {
+        let mut world = VoxelWorld::default();
+        world.set_voxel(IVec3::new(65, 2, 3), 5);
+        let mut svo = SvoStorage::default();
+        world.generate_svo(&mut svo);
+        assert!(svo.tree_scale as i32 > SECTOR_SCALE);
+        assert_eq!(lookup(&svo, IVec3::new(65, 2, 3)), 5);
+        assert_eq!(lookup(&svo, IVec3::new(1, 2, 3)), 0);
+    }
+
+    #[test]
+    fn lone_sector_at_origin_is_the_root() {
+        let mut world = VoxelWorld::default();
+        world.set_voxel(IVec3::new(1, 2, 3), 5);
+        let mut svo = SvoStorage::default();
+        world.generate_svo(&mut svo);
+        assert_eq!(svo.tree_scale as i32, SECTOR_SCALE);
+        assert_eq!(lookup(&svo, IVec3::new(1, 2, 3)), 5);
+    }
 }
@@ -0,0 +1,245 @@
+use crate::cellular::ActiveBricks;
+use crate::character::CameraSettings;
+use crate::config::AppSettings;
+use crate::generation::WorldGenerator;
+use crate::history::EditHistory;
+use crate::palette::{Palette, PaletteHandle, PaletteLoader, apply_palette};
+use crate::plugin::VoxelSet;
+use crate::rigid_body::VoxelBody;
+use crate::thermal::ThermalField;
+use crate::voxel_map::VoxelWorld;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// Config of the demo, relative to `assets/`.
+pub const CONFIG_FILE: &str = "settings.ron";
+
+pub const DEFAULT_PALETTE: &str = "default.palette.ron";
+
+/// Everything the demo reads from `CONFIG_FILE`. Missing fields keep their defaults.
+#[derive(Asset, TypePath, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldConfig {
+    /// Window size and render settings. `workgroup_size` only takes effect when the compute
+    /// worker is rebuilt on the next resize.
+    pub settings: AppSettings,
+    pub camera: CameraSettings,
+    /// Asset path of the `Palette`, relative to `assets/`.
+    pub palette: String,
+    /// Changing the generator regenerates the world, dropping edits, undo history, heat,
+    /// active cellular bricks and live voxel bodies.
+    pub generator: WorldGenerator,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            settings: AppSettings::default(),
+            camera: CameraSettings::default(),
+            palette: DEFAULT_PALETTE.to_string(),
+            generator: WorldGenerator::default(),
+        }
+    }
+}
+
+impl WorldConfig {
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        ron::de::from_bytes(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Reads the config without the asset server, for startup and headless runs.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[derive(Default)]
+pub struct WorldConfigLoader;
+
+impl AssetLoader for WorldConfigLoader {
+    type Asset = WorldConfig;
+    type Settings = ();
+    type Error = io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<WorldConfig, io::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        WorldConfig::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["settings.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct WorldConfigHandle(pub Handle<WorldConfig>);
+
+/// Loads a `WorldConfig` asset and applies it again whenever the file changes.
+pub struct VoxelConfigPlugin {
+    pub path: String,
+}
+
+impl Plugin for VoxelConfigPlugin {
+    fn build(&self, app: &mut App) {
+        let path = self.path.clone();
+        app.init_asset::<WorldConfig>()
+            .init_asset_loader::<WorldConfigLoader>()
+            .init_asset::<Palette>()
+            .init_asset_loader::<PaletteLoader>()
+            .add_systems(
+                Startup,
+                move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                    commands.insert_resource(WorldConfigHandle(asset_server.load(&path)));
+                },
+            )
+            .add_systems(
+                Update,
+                (
+                    apply_world_config.run_if(resource_exists::<WorldConfigHandle>),
+                    apply_palette.run_if(resource_exists::<PaletteHandle>),
+                )
+                    .chain()
+                    .in_set(VoxelSet::Input),
+            );
+    }
+}
+
+/// Applies the config when it is first loaded and on every hot reload. Only the parts that
+/// changed are written, so reloading an unchanged file does not regenerate the world.
+pub fn apply_world_config(
+    mut events: EventReader<AssetEvent<WorldConfig>>,
+    handle: Res<WorldConfigHandle>,
+    configs: Res<Assets<WorldConfig>>,
+    mut settings: ResMut<AppSettings>,
+    mut camera: ResMut<CameraSettings>,
+    mut generator: ResMut<WorldGenerator>,
+    mut world: ResMut<VoxelWorld>,
+    mut history: ResMut<EditHistory>,
+    mut active: ResMut<ActiveBricks>,
+    mut thermal: ResMut<ThermalField>,
+    bodies: Query<Entity, With<VoxelBody>>,
+    mut window_q: Query<&mut Window, With<PrimaryWindow>>,
+    palette_handle: Option<Res<PaletteHandle>>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    let reloaded = events
+        .read()
+        .filter(|e| e.is_loaded_with_dependencies(&handle.0) || e.is_modified(&handle.0))
+        .count()
+        > 0;
+    let Some(config) = configs.get(&handle.0).filter(|_| reloaded) else {
+        return;
+    };
+    println!("Applying world config");
+
+    if settings.set_if_neq(config.settings) {
+        if let Ok(mut window) = window_q.single_mut() {
+            window
+                .resolution
+                .set(config.settings.width as f32, config.settings.height as f32);
+        }
+    }
+    camera.set_if_neq(config.camera);
+    let palette_path = palette_handle.and_then(|h| h.0.path().map(|p| p.to_string()));
+    if palette_path.as_deref() != Some(config.palette.as_str()) {
+        commands.insert_resource(PaletteHandle(asset_server.load(&config.palette)));
+    }
+    if *generator != config.generator {
+        *generator = config.generator.clone();
+        world.sectors.clear();
+        generator.generate(&mut world);
+        history.clear();
+        // Simulation state refers to the old world, bodies would bake into the new one.
+        active.bricks.clear();
+        thermal.bricks.clear();
+        for entity in &bodies {
+            commands.entity(entity).despawn();
+        }
+        println!("World regenerated: {:?}", *generator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::WorldGenerator;
+    use crate::plugin::VoxelCorePlugin;
+    use crate::rigid_body::body_bundle;
+    use crate::thermal::ThermalBrick;
+    use crate::volume::VoxelVolume;
+
+    #[test]
+    fn new_generator_resets_simulation_state() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default(), VoxelCorePlugin))
+            .init_asset::<WorldConfig>()
+            .init_asset::<Palette>()
+            .init_resource::<AppSettings>()
+            .init_resource::<CameraSettings>()
+            .insert_resource(WorldGenerator::Empty)
+            .add_systems(
+                Update,
+                apply_world_config.run_if(resource_exists::<WorldConfigHandle>),
+            );
+        app.update();
+
+        let mut volume = VoxelVolume::new(IVec3::ONE);
+        volume.set(IVec3::ZERO, 1);
+        let body = VoxelBody::new(volume, &VoxelWorld::default(), 0.1).unwrap();
+        app.world_mut()
+            .spawn(body_bundle(body, IVec3::new(0, 50, 0)));
+        app.world_mut()
+            .resource_mut::<ActiveBricks>()
+            .bricks
+            .insert(IVec3::ONE);
+        let mut thermal = app.world_mut().resource_mut::<ThermalField>();
+        let brick = ThermalBrick {
+            temperature: [500.0; 64],
+            burn: [0.0; 64],
+        };
+        thermal.bricks.insert(IVec3::ONE, brick);
+
+        let config = WorldConfig {
+            generator: WorldGenerator::Sphere {
+                center: IVec3::splat(8),
+                radius: 4,
+                material: "stone".to_string(),
+            },
+            ..default()
+        };
+        let handle = app
+            .world_mut()
+            .resource_mut::<Assets<WorldConfig>>()
+            .add(config);
+        app.world_mut()
+            .send_event(AssetEvent::LoadedWithDependencies { id: handle.id() });
+        app.insert_resource(WorldConfigHandle(handle));
+        app.update();
+
+        assert!(
+            app.world()
+                .resource::<VoxelWorld>()
+                .is_solid(IVec3::splat(8))
+        );
+        assert!(app.world().resource::<ActiveBricks>().bricks.is_empty());
+        assert!(app.world().resource::<ThermalField>().bricks.is_empty());
+        let bodies = app
+            .world_mut()
+            .query::<&VoxelBody>()
+            .iter(app.world())
+            .count();
+        assert_eq!(bodies, 0);
+    }
+}
@@ -0,0 +1,77 @@
+use bevy::prelude::*;
+use mushoku_tensei::structure::{CollapseMode, StructuralSettings};
+use mushoku_tensei::{
+    Benchmark, CameraPath, SvoStorage, VoxelCorePlugin, VoxelWorld, VoxelsEdited, WorldGenerator,
+};
+
+/// The core has to run without a window, GPU or input, the way tests and dedicated servers
+/// use it. A render or input resource slipping into a core system panics here.
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, VoxelCorePlugin));
+    app
+}
+
+#[test]
+fn core_plugin_runs_under_minimal_plugins() {
+    let mut app = headless_app();
+    for _ in 0..5 {
+        app.update();
+    }
+    let world = app.world().resource::<VoxelWorld>();
+    assert!(
+        !world.sectors.is_empty(),
+        "default generator left the world empty"
+    );
+    let svo = app.world().resource::<SvoStorage>();
+    assert!(svo.nodes.len() > 1, "SVO was not built");
+}
+
+/// Places a floating row of voxels and reports it as edited, returns how many bodies exist
+/// after a few frames.
+fn detach_floating_row(app: &mut App) -> usize {
+    let mut world = app.world_mut().resource_mut::<VoxelWorld>();
+    for x in 0..3 {
+        world.set_voxel(IVec3::new(x, 20, 0), 1);
+    }
+    app.world_mut().send_event(VoxelsEdited {
+        min: IVec3::new(0, 20, 0),
+        max: IVec3::new(2, 20, 0),
+    });
+    for _ in 0..5 {
+        app.update();
+    }
+    app.world_mut()
+        .query::<&mushoku_tensei::rigid_body::VoxelBody>()
+        .iter(app.world())
+        .count()
+}
+
+fn empty_world_app() -> App {
+    let mut app = headless_app();
+    app.insert_resource(WorldGenerator::Empty)
+        .insert_resource(StructuralSettings {
+            collapse: CollapseMode::RigidBody,
+            ..default()
+        });
+    app.update();
+    app
+}
+
+#[test]
+fn core_plugin_simulates_edits_headless() {
+    // The floating row detaches into a body, simulated without a mesh.
+    assert_eq!(detach_floating_row(&mut empty_world_app()), 1);
+}
+
+#[test]
+fn simulation_is_paused_while_benchmarking() {
+    let mut app = empty_world_app();
+    app.insert_resource(Benchmark::new(CameraPath::default(), "test"));
+    assert_eq!(detach_floating_row(&mut app), 0);
+    assert!(
+        app.world()
+            .resource::<VoxelWorld>()
+            .is_solid(IVec3::new(1, 20, 0))
+    );
+}
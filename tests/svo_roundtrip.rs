@@ -0,0 +1,196 @@
+use bevy::math::{IVec3, Vec3};
+use bevy::platform::collections::HashMap;
+use mushoku_tensei::config::{Material, Node};
+use mushoku_tensei::voxel_map::{SvoStorage, VoxelWorld};
+use proptest::prelude::*;
+
+fn child_ptr(node: &Node) -> usize {
+    (node.packed_data[0] >> 2) as usize
+}
+
+fn is_leaf(node: &Node) -> bool {
+    node.packed_data[0] & 1 != 0
+}
+
+fn child_mask(node: &Node) -> u64 {
+    node.packed_data[1] as u64 | (node.packed_data[2] as u64) << 32
+}
+
+/// Offset of child slot `i`, the layout the shader uses: x + z * 4 + y * 16.
+fn slot_offset(i: u32) -> IVec3 {
+    IVec3::new(i as i32 & 3, (i as i32 >> 4) & 3, (i as i32 >> 2) & 3)
+}
+
+/// Reconstructs every solid voxel by walking the tree the way the ray marcher does.
+fn walk(svo: &SvoStorage, node: &Node, scale: u32, pos: IVec3, out: &mut HashMap<IVec3, u8>) {
+    let mask = child_mask(node);
+    let mut rank = 0;
+    for i in 0..64 {
+        if mask & (1 << i) == 0 {
+            continue;
+        }
+        let child_pos = pos + (slot_offset(i) << (scale as i32 - 2));
+        let index = child_ptr(node) + rank;
+        rank += 1;
+        if is_leaf(node) {
+            assert_eq!(scale, 2, "leaf above brick level at {}", pos);
+            let mat_id = svo.leaf_data[index];
+            assert!(
+                mat_id != 0 && mat_id < 256,
+                "bad material {} at {}",
+                mat_id,
+                child_pos
+            );
+            let previous = out.insert(child_pos, mat_id as u8);
+            assert!(previous.is_none(), "voxel {} reached twice", child_pos);
+        } else {
+            assert!(scale > 2, "inner node below brick level at {}", pos);
+            walk(svo, &svo.nodes[index], scale - 2, child_pos, out);
+        }
+    }
+}
+
+fn reconstruct(svo: &SvoStorage) -> HashMap<IVec3, u8> {
+    let mut out = HashMap::default();
+    walk(svo, &svo.nodes[0], svo.tree_scale, IVec3::ZERO, &mut out);
+    out
+}
+
+/// Writes `voxels` in order, later writes win, and returns what the world should contain.
+fn build(voxels: &[(IVec3, u8)]) -> (VoxelWorld, HashMap<IVec3, u8>) {
+    let mut world = VoxelWorld::default();
+    let mut expected = HashMap::default();
+    for &(pos, mat_id) in voxels {
+        world.set_voxel(pos, mat_id);
+        if mat_id == 0 {
+            expected.remove(&pos);
+        } else {
+            expected.insert(pos, mat_id);
+        }
+    }
+    (world, expected)
+}
+
+fn roundtrip(voxels: &[(IVec3, u8)]) -> Result<(), TestCaseError> {
+    let (world, expected) = build(voxels);
+    for (&pos, &mat_id) in &expected {
+        prop_assert_eq!(world.get_voxel(pos), mat_id, "get_voxel at {}", pos);
+    }
+    let mut svo = SvoStorage::default();
+    world.generate_svo(&mut svo);
+    prop_assert_eq!(reconstruct(&svo), expected);
+    Ok(())
+}
+
+fn voxel(extent: i32) -> impl Strategy<Value = (IVec3, u8)> {
+    (0..extent, 0..extent, 0..extent, 0..=255u8)
+        .prop_map(|(x, y, z, mat_id)| (IVec3::new(x, y, z), mat_id))
+}
+
+proptest! {
+    /// Scattered over several sectors, so the top level tree has to merge them.
+    #[test]
+    fn sparse_voxels_survive_svo_build(voxels in prop::collection::vec(voxel(300), 0..400)) {
+        roundtrip(&voxels)?;
+    }
+
+    /// Packed into a couple of bricks, so most bricks are partially filled and get
+    /// overwritten and emptied again.
+    #[test]
+    fn dense_voxels_survive_svo_build(voxels in prop::collection::vec(voxel(8), 0..600)) {
+        roundtrip(&voxels)?;
+    }
+
+    /// Whole filled boxes at random offsets, crossing brick and sector borders.
+    #[test]
+    fn boxes_survive_svo_build(
+        min in (0..200, 0..200, 0..200),
+        size in (1..20, 1..20, 1..20),
+        mat_id in 1..=255u8,
+    ) {
+        let min = IVec3::new(min.0, min.1, min.2);
+        let size = IVec3::new(size.0, size.1, size.2);
+        let mut voxels = Vec::new();
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    voxels.push((min + IVec3::new(x, y, z), mat_id));
+                }
+            }
+        }
+        roundtrip(&voxels)?;
+    }
+}
+
+#[test]
+fn single_voxel_positions_are_exact() {
+    for pos in [
+        IVec3::ZERO,
+        IVec3::new(1, 2, 3),
+        IVec3::new(3, 0, 0),
+        IVec3::new(0, 3, 0),
+        IVec3::new(0, 0, 3),
+        IVec3::new(5, 17, 63),
+        IVec3::new(64, 0, 0),
+        IVec3::new(0, 130, 70),
+    ] {
+        let (world, expected) = build(&[(pos, 7)]);
+        let mut svo = SvoStorage::default();
+        world.generate_svo(&mut svo);
+        assert_eq!(reconstruct(&svo), expected, "voxel at {}", pos);
+    }
+}
+
+/// Walks the tree and checks every node's LOD color and voxel count against the voxels below
+/// it. Both are rounded once per level, so some drift is allowed.
+fn check_lod(svo: &SvoStorage, node: &Node, scale: u32, palette: &[Material]) -> (Vec3, u32) {
+    let mask = child_mask(node);
+    let (mut sum, mut count) = (Vec3::ZERO, 0);
+    for rank in 0..mask.count_ones() as usize {
+        let index = child_ptr(node) + rank;
+        if is_leaf(node) {
+            sum += Vec3::from_array(palette[svo.leaf_data[index] as usize].color);
+            count += 1;
+        } else {
+            let (child_sum, child_count) = check_lod(svo, &svo.nodes[index], scale - 2, palette);
+            sum += child_sum;
+            count += child_count;
+        }
+    }
+    let (color, lod_count) = node.lod();
+    let error = (color - sum / count as f32).abs().max_element();
+    assert!(
+        error < 0.05,
+        "lod color {} of a node at scale {} averages to {}",
+        color,
+        scale,
+        sum / count as f32
+    );
+    assert!(
+        (lod_count / count as f32).log2().abs() < 0.5,
+        "node at scale {} holds {} voxels, lod count {}",
+        scale,
+        count,
+        lod_count
+    );
+    (sum, count)
+}
+
+proptest! {
+    /// Random materials over a couple of sectors, so nodes average different colors.
+    #[test]
+    fn lod_colors_average_voxels(voxels in prop::collection::vec(voxel(100), 1..300)) {
+        let (mut world, _) = build(&voxels);
+        world.palette = (0..256)
+            .map(|id| Material {
+                color: [(id % 7) as f32 / 6.0, (id % 3) as f32 / 2.0, (id % 11) as f32 / 10.0],
+                ..Default::default()
+            })
+            .collect();
+        let mut svo = SvoStorage::default();
+        world.generate_svo(&mut svo);
+        if !world.sectors.is_empty() {
+            check_lod(&svo, &svo.nodes[0], svo.tree_scale, &world.palette);
+        }
+    }
+}